@@ -1,7 +1,7 @@
 #![feature(test)]
 
 extern crate test;
-use owned_ref_cell::OwnedRefCell;
+use owned_ref_cell::{LocalOwnedRefCell, OwnedRefCell};
 use test::Bencher;
 
 #[bench]
@@ -65,3 +65,23 @@ fn bench_borrow_mut_after_borrow_mut_fails(b: &mut Bencher) {
         }
     });
 }
+
+#[bench]
+fn bench_local_borrow_mut(b: &mut Bencher) {
+    let cell = LocalOwnedRefCell::new(42);
+    b.iter(|| {
+        for _ in 0..1000 {
+            test::black_box(cell.borrow_mut());
+        }
+    });
+}
+
+#[bench]
+fn bench_local_borrow(b: &mut Bencher) {
+    let cell = LocalOwnedRefCell::new(42);
+    b.iter(|| {
+        for _ in 0..1000 {
+            test::black_box(cell.borrow());
+        }
+    });
+}
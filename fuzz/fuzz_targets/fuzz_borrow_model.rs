@@ -0,0 +1,25 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use owned_ref_cell::{run_borrow_model, ModelOp};
+use std::vec::Vec;
+
+fuzz_target!(|data: &[u8]| {
+    let ops: Vec<ModelOp> = data
+        .iter()
+        .map(|&byte| {
+            let idx = usize::from(byte >> 3);
+            match byte % 6 {
+                0 => ModelOp::Borrow,
+                1 => ModelOp::BorrowMut,
+                2 => ModelOp::Drop(idx),
+                3 => ModelOp::Downgrade(idx),
+                4 => ModelOp::Upgrade(idx),
+                _ => ModelOp::Split(idx),
+            }
+        })
+        .collect();
+    if let Err(violation) = run_borrow_model(&ops) {
+        panic!("{}", violation);
+    }
+});
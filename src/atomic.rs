@@ -0,0 +1,240 @@
+//! A thread-safe variant of `OwnedRefCell`.
+
+use alloc::sync::Arc;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// The borrow counter value while an `AtomicOwnedRefMut` is alive.
+const WRITING: usize = usize::MAX;
+
+/// A thread-safe `OwnedRefCell`, whose references can be sent to and shared with other threads.
+///
+/// The borrow rules are the same as for `OwnedRefCell`: any number of `AtomicOwnedRef`s, or a
+/// single `AtomicOwnedRefMut`. The borrow state is a single atomic counter, and a borrow that
+/// conflicts with another thread's fails instead of waiting for it, so `try_borrow` and
+/// `try_borrow_mut` return `None` and `borrow` and `borrow_mut` panic. For blocking access, use a
+/// `RwLock` instead.
+///
+/// The value and the counter live in one `Arc` allocation that the references share, so a
+/// reference stays valid after the cell itself is dropped. Like the `LocalOwnedRefCell`, the cell
+/// offers only the basic borrow operations.
+///
+/// ```
+/// use owned_ref_cell::AtomicOwnedRefCell;
+/// use std::thread;
+///
+/// let cell = AtomicOwnedRefCell::new(vec![1, 2]);
+/// let mut values = cell.borrow_mut();
+/// thread::spawn(move || values.push(3)).join().unwrap();
+/// assert_eq!(*cell.borrow(), [1, 2, 3]);
+/// ```
+pub struct AtomicOwnedRefCell<T> {
+    inner: Arc<AtomicInner<T>>,
+}
+
+/// The allocation shared by an `AtomicOwnedRefCell` and its references.
+struct AtomicInner<T> {
+    /// The number of live `AtomicOwnedRef`s, or `WRITING` while an `AtomicOwnedRefMut` is alive.
+    borrow: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send + Sync> Sync for AtomicInner<T> {}
+
+/// An immutable reference to the value inside an `AtomicOwnedRefCell`.
+pub struct AtomicOwnedRef<T> {
+    inner: Arc<AtomicInner<T>>,
+}
+
+/// A mutable reference to the value inside an `AtomicOwnedRefCell`.
+pub struct AtomicOwnedRefMut<T> {
+    inner: Arc<AtomicInner<T>>,
+}
+
+impl<T> AtomicOwnedRefCell<T> {
+    /// Constructs a new `AtomicOwnedRefCell` with the specified value.
+    pub fn new(value: T) -> Self {
+        AtomicOwnedRefCell {
+            inner: Arc::new(AtomicInner {
+                borrow: AtomicUsize::new(0),
+                value: UnsafeCell::new(value),
+            }),
+        }
+    }
+
+    /// Borrows the cell immutably.
+    /// Panics if the cell is already borrowed mutably.
+    pub fn borrow(&self) -> AtomicOwnedRef<T> {
+        self.try_borrow()
+            .expect("Failed to borrow: already mutably borrowed")
+    }
+
+    /// Borrows the cell mutably.
+    /// Panics if the cell is already borrowed.
+    pub fn borrow_mut(&self) -> AtomicOwnedRefMut<T> {
+        self.try_borrow_mut()
+            .expect("Failed to borrow mutably: already borrowed")
+    }
+
+    /// Tries to borrow the cell immutably, returning `None` if it is already borrowed mutably.
+    pub fn try_borrow(&self) -> Option<AtomicOwnedRef<T>> {
+        self.inner
+            .borrow
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |readers| {
+                (readers < WRITING - 1).then(|| readers + 1)
+            })
+            .ok()?;
+        Some(AtomicOwnedRef {
+            inner: Arc::clone(&self.inner),
+        })
+    }
+
+    /// Tries to borrow the cell mutably, returning `None` if it is already borrowed.
+    pub fn try_borrow_mut(&self) -> Option<AtomicOwnedRefMut<T>> {
+        self.inner
+            .borrow
+            .compare_exchange(0, WRITING, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+        Some(AtomicOwnedRefMut {
+            inner: Arc::clone(&self.inner),
+        })
+    }
+}
+
+/// Implements `Deref` for `AtomicOwnedRef` to allow dereferencing the immutable reference.
+impl<T> Deref for AtomicOwnedRef<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.inner.value.get() }
+    }
+}
+
+/// Implements `Drop` for `AtomicOwnedRef` to release its read borrow.
+impl<T> Drop for AtomicOwnedRef<T> {
+    fn drop(&mut self) {
+        self.inner.borrow.fetch_sub(1, Ordering::Release);
+    }
+}
+
+/// Implements `Deref` for `AtomicOwnedRefMut` to allow dereferencing the mutable reference.
+impl<T> Deref for AtomicOwnedRefMut<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.inner.value.get() }
+    }
+}
+
+/// Implements `DerefMut` for `AtomicOwnedRefMut` to allow mutating the referenced value.
+impl<T> DerefMut for AtomicOwnedRefMut<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.inner.value.get() }
+    }
+}
+
+/// Implements `Drop` for `AtomicOwnedRefMut` to release its write borrow.
+impl<T> Drop for AtomicOwnedRefMut<T> {
+    fn drop(&mut self) {
+        self.inner.borrow.store(0, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Barrier;
+    use std::thread;
+
+    const ROUNDS: usize = if cfg!(miri) { 20 } else { 200 };
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn references_are_send_and_sync() {
+        assert_send_sync::<AtomicOwnedRefCell<Vec<i32>>>();
+        assert_send_sync::<AtomicOwnedRef<Vec<i32>>>();
+        assert_send_sync::<AtomicOwnedRefMut<Vec<i32>>>();
+    }
+
+    #[test]
+    fn readers_xor_writer() {
+        let cell = AtomicOwnedRefCell::new(1);
+        let first = cell.borrow();
+        let second = cell.borrow();
+        assert!(cell.try_borrow_mut().is_none());
+        drop((first, second));
+
+        let mut writer = cell.borrow_mut();
+        *writer += 1;
+        assert!(cell.try_borrow().is_none());
+        assert!(cell.try_borrow_mut().is_none());
+        drop(writer);
+        assert_eq!(*cell.borrow(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Failed to borrow mutably: already borrowed")]
+    fn panic_on_borrow_mut_while_borrowed() {
+        let cell = AtomicOwnedRefCell::new(1);
+        let _reader = cell.borrow();
+        let _writer = cell.borrow_mut();
+    }
+
+    #[test]
+    fn reference_outlives_cell() {
+        let cell = AtomicOwnedRefCell::new(String::from("value"));
+        let reader = cell.borrow();
+        drop(cell);
+        assert_eq!(*reader, "value");
+    }
+
+    #[test]
+    fn reference_released_on_another_thread() {
+        let cell = AtomicOwnedRefCell::new(0);
+        let mut writer = cell.borrow_mut();
+        thread::spawn(move || *writer += 1).join().unwrap();
+        assert_eq!(*cell.borrow(), 1);
+    }
+
+    #[test]
+    fn concurrent_readers_and_writers() {
+        const THREADS: usize = 4;
+
+        let cell = Arc::new(AtomicOwnedRefCell::new((0usize, 0usize)));
+        let barrier = Arc::new(Barrier::new(2 * THREADS));
+        let mut handles = Vec::new();
+        for _ in 0..THREADS {
+            let (writer_cell, writer_barrier) = (Arc::clone(&cell), Arc::clone(&barrier));
+            handles.push(thread::spawn(move || {
+                writer_barrier.wait();
+                let mut writes = 0;
+                while writes < ROUNDS {
+                    if let Some(mut pair) = writer_cell.try_borrow_mut() {
+                        pair.0 += 1;
+                        thread::yield_now();
+                        pair.1 += 1;
+                        writes += 1;
+                    }
+                }
+            }));
+            let (reader_cell, reader_barrier) = (Arc::clone(&cell), Arc::clone(&barrier));
+            handles.push(thread::spawn(move || {
+                reader_barrier.wait();
+                for _ in 0..ROUNDS {
+                    if let Some(pair) = reader_cell.try_borrow() {
+                        let first = pair.0;
+                        thread::yield_now();
+                        assert_eq!(first, pair.1);
+                    }
+                }
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(*cell.borrow(), (THREADS * ROUNDS, THREADS * ROUNDS));
+        assert!(cell.try_borrow_mut().is_some());
+    }
+}
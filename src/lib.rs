@@ -23,6 +23,13 @@
 //!   `OwnedRefCell` also offers methods (`try_borrow` and `try_borrow_mut`) that return `None` when
 //!   a borrow would violate the rules, allowing the caller to react without forcing a panic.
 //!
+//! # `no_std`
+//!
+//! The crate depends on `std` only through the default `std` feature. With
+//! `default-features = false` it builds for targets that provide `core` and `alloc`, without the
+//! `HashMap` and `io` integrations and the features that keep thread-local state
+//! (`depth_tracking`, `leak-detect`, `log` and `recursion_detect`).
+//!
 //! # Examples
 //!
 //! Basic usage:
@@ -64,254 +71,3507 @@
 //! - `OwnedRef<T>`: an owned, immutable reference to the value inside an `OwnedRefCell<T>`.
 //! - `OwnedRefMut<T>`: an owned, mutable reference to the value inside an `OwnedRefCell<T>`.
 
-use std::cell::{RefCell, UnsafeCell};
-use std::ops::{Deref, DerefMut};
-use std::rc::Rc;
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+use alloc::rc::{Rc, Weak};
+#[cfg(not(any(feature = "std", test)))]
+use alloc::{boxed::Box, format, string::String, vec::Vec};
+#[cfg(any(
+    test,
+    feature = "async",
+    feature = "leak-detect",
+    feature = "recursion_detect",
+    feature = "test-util"
+))]
+use core::cell::RefCell;
+use core::cell::{Cell, OnceCell, UnsafeCell};
+use core::cmp::Ordering;
+use core::error::Error;
+use core::fmt;
+#[cfg(feature = "async")]
+use core::future::Future;
+use core::hash::{Hash, Hasher};
+use core::marker::PhantomData;
+use core::mem::{self, ManuallyDrop};
+use core::ops::{Deref, DerefMut, Range};
+use core::panic::Location;
+#[cfg(feature = "async")]
+use core::pin::Pin;
+use core::ptr::{self, NonNull};
+#[cfg(feature = "async")]
+use core::task::{Context, Poll, Waker};
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "recursion_detect")]
+use std::collections::HashSet;
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "log")]
+use std::time::Instant;
+
+#[cfg(target_has_atomic = "ptr")]
+mod atomic;
+
+#[cfg(target_has_atomic = "ptr")]
+pub use atomic::{AtomicOwnedRef, AtomicOwnedRefCell, AtomicOwnedRefMut};
 
 /// Provides mutable or immutable access to encapsulated value with owned references.
-pub struct OwnedRefCell<T> {
-    value: UnsafeCell<T>,
-    state: Rc<RefCell<BorrowState>>,
+///
+/// The value is kept in a heap allocation of its own, so an `OwnedRefCell` is always sized, even
+/// for an unsized `T` such as a slice or a trait object.
+///
+/// There is no counterpart to `Cell::as_slice_of_cells`: an `OwnedRefCell<[T]>` has a single
+/// borrow state for the whole slice, so its elements cannot be reinterpreted as cells of their
+/// own. Use `OwnedRefMut::split_at_mut` to borrow several elements mutably at once instead.
+///
+/// If the cell is dropped while references to it are still alive, the value is kept alive until
+/// the last of them has been dropped, so references never dangle. The value is then dropped
+/// along with that last reference, unless it is a reference to a component of the value made
+/// with `OwnedRef::map` or the like: such a reference is not tied to the lifetimes in `T`, so the
+/// value may no longer be safe to drop, and it is leaked instead.
+pub struct OwnedRefCell<T: ?Sized> {
+    state: Rc<BorrowState>,
+    /// Owned like a `Box<T>`, but kept as a raw pointer so that moving the cell does not
+    /// invalidate the pointers held by its references. For a fork that still shares the value
+    /// of the cell it was made from, this points at that value instead, see `fork_clone`.
+    value: Cell<NonNull<T>>,
+    /// Makes the copy of the shared value of a fork on its first write, see `OwnedRefCell::fork`.
+    fork_clone: Cell<Option<CloneFn<T>>>,
 }
 
+/// Makes a boxed copy of a value, see `OwnedRefCell::fork`.
+type CloneFn<T> = fn(&T) -> Box<T>;
+
 /// Internal state to keep track of the borrowing status.
+#[derive(Default)]
 struct BorrowState {
-    is_writing: bool,
-    reading_count: usize,
+    is_writing: Cell<bool>,
+    /// Number of live `OwnedRefMut`s sharing the write borrow, which is only more than one after
+    /// a mutable reference has been split.
+    writing_count: Cell<usize>,
+    reading_count: Cell<usize>,
+    /// Number of write borrows released so far, see `OwnedRefCell::version`.
+    version: Cell<u64>,
+    /// Set once the cell has been dropped, after which `WeakOwnedRef`s can no longer upgrade.
+    cell_dropped: Cell<bool>,
+    /// Number of live references to a component of the value, see `OwnedRefCell`.
+    projected: Cell<usize>,
+    /// The value of the cell if it was dropped while still borrowed, kept alive for its
+    /// remaining references.
+    orphan: Cell<Option<OrphanedValue>>,
+    /// Read borrow of the cell a fork was made from, held for as long as references to the
+    /// shared value may be alive, see `OwnedRefCell::fork`.
+    fork_source: Cell<Option<ForkSource>>,
+    /// Set once a fork has made its own copy of the value, after which `fork_source` is released
+    /// as soon as the fork is no longer borrowed.
+    fork_detached: Cell<bool>,
+    #[cfg(feature = "diagnostics")]
+    writer_location: Cell<Option<&'static Location<'static>>>,
+    /// Id of the most recently issued guard, see `OwnedRef::id`.
+    #[cfg(feature = "diagnostics")]
+    last_guard_id: Cell<u64>,
+    /// Tag and start time of a write borrow taken with `borrow_mut_logged`.
+    #[cfg(feature = "log")]
+    logged_write: Cell<Option<(&'static str, Instant)>>,
+    /// Every transition of this state so far, see `borrow_ops`.
+    #[cfg(any(test, feature = "test-util"))]
+    ops: RefCell<Vec<BorrowOp>>,
+    /// Tasks waiting for the cell to become free, see `register_waker`.
+    #[cfg(feature = "async")]
+    wakers: RefCell<Vec<Waker>>,
+    /// Callback to run once the current write borrow is released by all the references sharing
+    /// it, see `OwnedRefCell::borrow_mut_with_callback`.
+    #[cfg(feature = "callbacks")]
+    write_callback: Cell<Option<Box<dyn FnOnce()>>>,
+}
+
+/// The type-erased value of an `OwnedRefCell` that was dropped while still borrowed.
+struct OrphanedValue {
+    /// Points to the value pointer of the cell, boxed so it is a thin pointer.
+    value: *mut (),
+    drop: unsafe fn(*mut ()),
+}
+
+/// The type-erased `OwnedRef` that a fork reads the value of the cell it was made from through.
+struct ForkSource {
+    /// Points to the boxed `OwnedRef`.
+    guard: *mut (),
+    drop: unsafe fn(*mut ()),
 }
 
 /// An immutable reference to the value within `OwnedRefCell`.
-pub struct OwnedRef<T> {
+pub struct OwnedRef<T: ?Sized> {
     value: *const T,
-    state: Rc<RefCell<BorrowState>>,
+    state: Rc<BorrowState>,
+    /// Callback to run once this reference is dropped, see `OwnedRefCell::borrow_with_callback`.
+    #[cfg(feature = "callbacks")]
+    on_release: Option<Box<dyn FnOnce()>>,
+    version: u64,
+    /// Whether `value` may be written through, which is not the case once it was derived from a
+    /// shared reference by `OwnedRef::map` or the like; see `OwnedRef::try_upgrade`.
+    writable: bool,
+    /// Whether `value` points at a component of the value, counted in `BorrowState::projected`.
+    projected: bool,
+    #[cfg(feature = "diagnostics")]
+    id: u64,
+    #[cfg(feature = "recursion_detect")]
+    recursion_key: Option<usize>,
+}
+
+/// A weak handle to an `OwnedRefCell`, returned by `OwnedRefCell::downgrade`, that can be
+/// upgraded to an immutable reference for as long as the cell is alive.
+///
+/// It does not borrow the cell or keep it alive, like a `Weak` does not keep an `Rc` alive. This
+/// suits callbacks that should only read the cell if it still exists:
+///
+/// ```
+/// use owned_ref_cell::OwnedRefCell;
+///
+/// let cell = OwnedRefCell::new(1);
+/// let weak = cell.downgrade();
+/// let read = move || weak.upgrade().map(|value| *value);
+/// assert_eq!(read(), Some(1));
+///
+/// let writer = cell.borrow_mut();
+/// assert_eq!(read(), None);
+/// drop(writer);
+///
+/// drop(cell);
+/// assert_eq!(read(), None);
+/// ```
+pub struct WeakOwnedRef<T: ?Sized> {
+    value: *const T,
+    state: Weak<BorrowState>,
 }
 
 /// A mutable reference to the value within `OwnedRefCell`.
-pub struct OwnedRefMut<T> {
+pub struct OwnedRefMut<T: ?Sized> {
     value: *mut T,
-    state: Rc<RefCell<BorrowState>>,
+    state: Rc<BorrowState>,
+    /// Whether `value` points at a component of the value, counted in `BorrowState::projected`.
+    projected: bool,
+    #[cfg(feature = "diagnostics")]
+    id: u64,
+    #[cfg(feature = "recursion_detect")]
+    reentrancy_key: Option<usize>,
 }
 
-impl<T> OwnedRefCell<T> {
-    /// Constructs a new `OwnedRefCell` with the specified value.
-    pub fn new(value: T) -> Self {
-        OwnedRefCell {
-            value: UnsafeCell::new(value),
-            state: Rc::new(RefCell::new(BorrowState {
-                is_writing: false,
-                reading_count: 0,
-            })),
+/// A lazily computed value cached in an `OwnedRefCell`, handing out shared `OwnedRef`s to it.
+///
+/// ```
+/// use owned_ref_cell::Memo;
+///
+/// let memo = Memo::new();
+/// let value = memo.get_or_compute(|| 6 * 7);
+/// assert_eq!(*value, 42);
+/// drop(value);
+///
+/// memo.invalidate();
+/// assert_eq!(*memo.get_or_compute(|| 0), 0);
+/// ```
+pub struct Memo<T> {
+    cell: OwnedRefCell<Option<T>>,
+}
+
+/// A detached copy of the value of an `OwnedRefCell`, returned by `OwnedRefCell::snapshot`.
+///
+/// Unlike an `OwnedRef`, a snapshot does not borrow the cell, so writers are not blocked while
+/// it is alive and it does not observe their changes.
+pub struct Snapshot<T> {
+    value: T,
+}
+
+/// A mutable reference to the value of an `OwnedRefCell` nested in another one, returned by
+/// `OwnedRefCell::borrow_nested_mut`.
+///
+/// It holds a read borrow of the outer cell alongside the write borrow of the inner one, so the
+/// inner cell can't be replaced while the reference is alive.
+pub struct NestedRefMut<T: ?Sized> {
+    // Declared first so that the inner borrow is released before the outer one.
+    inner: OwnedRefMut<T>,
+    _outer: OwnedRef<OwnedRefCell<T>>,
+}
+
+/// A mutable reference to the value within `OwnedRefCell` that rolls back all changes made
+/// through it unless they are committed, returned by `OwnedRefCell::begin_mut`.
+///
+/// ```
+/// use owned_ref_cell::{OwnedRefCell, TxnGuard};
+///
+/// let cell = OwnedRefCell::new(vec![1]);
+///
+/// let mut txn = cell.begin_mut();
+/// txn.push(2);
+/// drop(txn);
+/// assert_eq!(*cell.borrow(), [1]);
+///
+/// let mut txn = cell.begin_mut();
+/// txn.push(3);
+/// TxnGuard::commit(txn);
+/// assert_eq!(*cell.borrow(), [1, 3]);
+/// ```
+pub struct TxnGuard<T> {
+    guard: OwnedRefMut<T>,
+    snapshot: Option<T>,
+}
+
+/// An immutable reference to the value within `OwnedRefCell` that dereferences to a value
+/// derived from it, returned by `OwnedRefCell::borrow_computed`.
+///
+/// The derived value is computed once, on first dereference, and cached for as long as the
+/// reference is alive. The cell stays borrowed immutably until then, so the cached value can't
+/// go stale.
+pub struct ComputedRef<T: ?Sized, U> {
+    guard: OwnedRef<T>,
+    compute: Box<dyn Fn(&T) -> U>,
+    cached: OnceCell<U>,
+}
+
+/// A future that resolves to a mutable reference once the cell is free, returned by
+/// `OwnedRefCell::borrow_mut_async`.
+#[cfg(feature = "async")]
+pub struct BorrowMutFuture<'a, T: ?Sized> {
+    cell: &'a OwnedRefCell<T>,
+}
+
+/// An `OwnedRefCell` that lets code which already holds the mutable borrow take it again, for
+/// algorithms such as tree mutation that re-enter a cell further down the call stack.
+///
+/// Every nested `borrow_mut` adds one level of depth to the existing write borrow, and the cell
+/// only becomes available again once all of them have been dropped. Because the nested
+/// references alias the same value, `borrow_mut` is `unsafe`: the caller must make sure that
+/// no two of them are used to access the value in an interleaved way.
+///
+/// ```
+/// use owned_ref_cell::ReentrantOwnedRefCell;
+///
+/// let cell = ReentrantOwnedRefCell::new(0);
+/// let mut outer = unsafe { cell.borrow_mut() };
+/// *outer += 1;
+/// {
+///     // `outer` is not touched while `inner` is alive.
+///     let mut inner = unsafe { cell.borrow_mut() };
+///     *inner += 1;
+///     assert_eq!(cell.depth(), 2);
+/// }
+/// *outer += 1;
+/// drop(outer);
+/// assert_eq!(*cell.borrow(), 3);
+/// ```
+pub struct ReentrantOwnedRefCell<T: ?Sized> {
+    cell: OwnedRefCell<T>,
+}
+
+/// A leaner `OwnedRefCell` for hot paths, whose references point straight at the cell's
+/// allocation instead of sharing its borrow state through an `Rc`.
+///
+/// Taking a `LocalOwnedRef` or `LocalOwnedRefMut` only updates a single borrow counter, with no
+/// reference count to bump. The value and the counter live in one heap allocation; if the cell is
+/// dropped while references are still alive, the allocation is kept and freed by the last of them.
+/// In exchange the cell offers only the basic borrow operations, without the diagnostics,
+/// projections and other extras of `OwnedRefCell`.
+///
+/// ```
+/// use owned_ref_cell::LocalOwnedRefCell;
+///
+/// let cell = LocalOwnedRefCell::new(1);
+/// let mut value = cell.borrow_mut();
+/// *value += 1;
+/// assert!(cell.try_borrow().is_none());
+/// drop(value);
+/// assert_eq!(*cell.borrow(), 2);
+/// ```
+pub struct LocalOwnedRefCell<T> {
+    inner: NonNull<LocalInner<T>>,
+    _marker: PhantomData<T>,
+}
+
+/// The allocation shared by a `LocalOwnedRefCell` and its references.
+struct LocalInner<T> {
+    /// The number of live `LocalOwnedRef`s, or -1 while a `LocalOwnedRefMut` is alive.
+    borrow: Cell<isize>,
+    /// Whether the `LocalOwnedRefCell` has been dropped, leaving the last reference to free
+    /// the allocation.
+    cell_dropped: Cell<bool>,
+    value: UnsafeCell<T>,
+}
+
+/// An immutable reference to the value inside a `LocalOwnedRefCell`.
+pub struct LocalOwnedRef<T> {
+    inner: NonNull<LocalInner<T>>,
+    _marker: PhantomData<T>,
+}
+
+/// A mutable reference to the value inside a `LocalOwnedRefCell`.
+pub struct LocalOwnedRefMut<T> {
+    inner: NonNull<LocalInner<T>>,
+    _marker: PhantomData<*mut T>,
+}
+
+/// A transition of the borrow state of a cell, as recorded for `OwnedRefCell::borrow_ops`.
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BorrowOp {
+    /// A read borrow was added.
+    Borrow,
+    /// A read borrow was released.
+    Release,
+    /// A write borrow was taken or shared with another `OwnedRefMut`.
+    BorrowMut,
+    /// One `OwnedRefMut`'s share of the write borrow was released.
+    ReleaseMut,
+    /// The sole read borrow was upgraded to a write borrow.
+    Upgrade,
+}
+
+/// An operation applied by `run_borrow_model` to a cell of the model.
+///
+/// Operations that take an index refer to the references the model currently holds, in the
+/// order they were made; an operation whose index is out of range, or that is refused by the
+/// borrow rules, is recorded as not applied and leaves the model unchanged.
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelOp {
+    /// Takes an `OwnedRef` with `try_borrow`.
+    Borrow,
+    /// Takes an `OwnedRefMut` with `try_borrow_mut`.
+    BorrowMut,
+    /// Drops the reference at the given index.
+    Drop(usize),
+    /// Converts the `OwnedRefMut` at the given index into an `OwnedRef`.
+    Downgrade(usize),
+    /// Upgrades the `OwnedRef` at the given index with `OwnedRef::try_upgrade`.
+    Upgrade(usize),
+    /// Splits the `OwnedRefMut` at the given index in two with `OwnedRefMut::split_at_mut`.
+    Split(usize),
+}
+
+/// The outcome of one step of `run_borrow_model`.
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModelStep {
+    /// The operation of this step.
+    pub op: ModelOp,
+    /// Whether the operation was applied, rather than refused or out of range.
+    pub applied: bool,
+    /// The number of immutable borrows of the cell after the step.
+    pub readers: usize,
+    /// Whether the cell was borrowed mutably after the step.
+    pub writing: bool,
+}
+
+/// An error returned by `run_borrow_model` when the cell did not behave like the model.
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelViolation {
+    /// The index of the step after which the violation was detected; equal to the number of
+    /// operations if it was detected once all references were dropped.
+    pub step: usize,
+    /// What the cell did differently from the model.
+    pub message: String,
+}
+
+/// An error returned when the cell could not be borrowed immutably because it is already borrowed
+/// mutably.
+#[derive(Debug)]
+pub struct BorrowError {
+    _private: (),
+}
+
+/// An error returned when the cell could not be borrowed mutably because it is already borrowed.
+#[derive(Debug)]
+pub struct BorrowMutError {
+    _private: (),
+}
+
+/// An error returned by `borrow_recursive` when the cell is already borrowed further up the
+/// current call chain.
+#[cfg(feature = "recursion_detect")]
+#[derive(Debug)]
+pub struct RecursionError {
+    _private: (),
+}
+
+/// An error returned by `borrow_mut_guarded` when the cell is already borrowed mutably further
+/// up the current call chain.
+#[cfg(feature = "recursion_detect")]
+#[derive(Debug)]
+pub struct ReentrantBorrowError {
+    _private: (),
+}
+
+#[cfg(feature = "recursion_detect")]
+thread_local! {
+    /// Identities of the cells that currently have an active `borrow_recursive` guard.
+    static RECURSIVE_BORROWS: RefCell<HashSet<usize>> = RefCell::new(HashSet::new());
+    /// Identities of the cells that currently have an active `borrow_mut_guarded` guard.
+    static GUARDED_WRITES: RefCell<HashSet<usize>> = RefCell::new(HashSet::new());
+}
+
+/// An error returned by `borrow_guarded` when the current thread already holds as many
+/// references as the given limit allows.
+#[cfg(feature = "depth_tracking")]
+#[derive(Debug)]
+pub struct DepthLimitError {
+    _private: (),
+}
+
+#[cfg(feature = "depth_tracking")]
+thread_local! {
+    /// Number of `OwnedRef`s and `OwnedRefMut`s currently alive on this thread.
+    static BORROW_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Returns the number of `OwnedRef`s and `OwnedRefMut`s, across all cells, that are currently
+/// alive on this thread. In a recursive walk that borrows each node on the way down, this is
+/// how deeply nested the walk is.
+///
+/// Only available with the `depth_tracking` feature.
+#[cfg(feature = "depth_tracking")]
+pub fn borrow_depth() -> usize {
+    BORROW_DEPTH.with(Cell::get)
+}
+
+/// Records that a new reference has been issued on this thread.
+#[cfg(feature = "depth_tracking")]
+fn enter_guard() {
+    BORROW_DEPTH.with(|depth| depth.set(depth.get() + 1));
+}
+
+/// Records that a reference issued on this thread has been dropped.
+#[cfg(feature = "depth_tracking")]
+fn exit_guard() {
+    BORROW_DEPTH.with(|depth| depth.set(depth.get() - 1));
+}
+
+/// Applies `ops` to a fresh cell one after the other and checks the borrow state against a model
+/// of the references alive after each step, returning the outcome of every step.
+///
+/// The invariants checked after each step are:
+///
+/// - an operation is applied exactly when the borrow rules allow it: `Borrow` while there is no
+///   `OwnedRefMut`, `BorrowMut` while there is no reference at all, `Upgrade` on the only
+///   reference, and `Downgrade` and `Split` on any `OwnedRefMut`,
+/// - the reader count of the cell equals the number of `OwnedRef`s alive,
+/// - the cell is borrowed mutably exactly while an `OwnedRefMut` is alive,
+/// - the internal counters of the borrow state agree with each other (in debug builds).
+///
+/// Once all operations are applied the remaining references are dropped, and the cell must be
+/// unborrowed. This is a test helper for fuzzing sequences of borrows, and is only available in
+/// this crate's tests or with the `test-util` feature.
+///
+/// ```
+/// use owned_ref_cell::{run_borrow_model, ModelOp};
+///
+/// let steps = run_borrow_model(&[ModelOp::Borrow, ModelOp::BorrowMut, ModelOp::Upgrade(0)])
+///     .unwrap();
+/// let applied: Vec<bool> = steps.iter().map(|step| step.applied).collect();
+/// assert_eq!(applied, [true, false, true]);
+/// assert!(steps[2].writing);
+/// ```
+#[cfg(any(test, feature = "test-util"))]
+pub fn run_borrow_model(ops: &[ModelOp]) -> Result<Vec<ModelStep>, ModelViolation> {
+    enum Held {
+        Read(OwnedRef<[u32]>),
+        Write(OwnedRefMut<[u32]>),
+    }
+
+    let cell = OwnedRefCell::<[u32]>::from_box(Box::new([0; 8]));
+    let mut held: Vec<Held> = Vec::new();
+    let mut steps = Vec::with_capacity(ops.len());
+    for (step, &op) in ops.iter().enumerate() {
+        let writers = held.iter().filter(|h| matches!(h, Held::Write(_))).count();
+        let expected = match op {
+            ModelOp::Borrow => writers == 0,
+            ModelOp::BorrowMut => held.is_empty(),
+            ModelOp::Drop(idx) => idx < held.len(),
+            ModelOp::Upgrade(idx) => {
+                held.len() == 1 && matches!(held.get(idx), Some(Held::Read(_)))
+            }
+            ModelOp::Downgrade(idx) | ModelOp::Split(idx) => {
+                matches!(held.get(idx), Some(Held::Write(_)))
+            }
+        };
+        let applied = match op {
+            ModelOp::Borrow => cell
+                .try_borrow()
+                .map(|r| held.push(Held::Read(r)))
+                .is_some(),
+            ModelOp::BorrowMut => cell
+                .try_borrow_mut()
+                .map(|w| held.push(Held::Write(w)))
+                .is_some(),
+            ModelOp::Drop(idx) => {
+                idx < held.len() && {
+                    held.remove(idx);
+                    true
+                }
+            }
+            ModelOp::Upgrade(idx) => match held.get(idx) {
+                Some(Held::Read(_)) => {
+                    let Held::Read(r) = held.remove(idx) else {
+                        unreachable!()
+                    };
+                    match OwnedRef::try_upgrade(r) {
+                        Ok(w) => {
+                            held.insert(idx, Held::Write(w));
+                            true
+                        }
+                        Err(r) => {
+                            held.insert(idx, Held::Read(r));
+                            false
+                        }
+                    }
+                }
+                _ => false,
+            },
+            ModelOp::Downgrade(idx) => match held.get(idx) {
+                Some(Held::Write(_)) => {
+                    let Held::Write(w) = held.remove(idx) else {
+                        unreachable!()
+                    };
+                    held.insert(idx, Held::Read(OwnedRef::from(w)));
+                    true
+                }
+                _ => false,
+            },
+            ModelOp::Split(idx) => match held.get(idx) {
+                Some(Held::Write(w)) => {
+                    let mid = w.len() / 2;
+                    let Held::Write(w) = held.remove(idx) else {
+                        unreachable!()
+                    };
+                    let (left, right) = OwnedRefMut::split_at_mut(w, mid);
+                    held.insert(idx, Held::Write(left));
+                    held.push(Held::Write(right));
+                    true
+                }
+                _ => false,
+            },
+        };
+        if applied != expected {
+            return Err(ModelViolation {
+                step,
+                message: format!("{:?} was applied: {}, expected: {}", op, applied, expected),
+            });
+        }
+
+        cell.state.assert_consistent();
+        let (readers, writing) = cell.borrow_stats();
+        let expected_readers = held.iter().filter(|h| matches!(h, Held::Read(_))).count();
+        let expected_writing = held.iter().any(|h| matches!(h, Held::Write(_)));
+        if readers != expected_readers || writing != expected_writing {
+            return Err(ModelViolation {
+                step,
+                message: format!(
+                    "cell has {} reader(s) and writing: {}, expected {} reader(s) and writing: {}",
+                    readers, writing, expected_readers, expected_writing
+                ),
+            });
         }
+        steps.push(ModelStep {
+            op,
+            applied,
+            readers,
+            writing,
+        });
     }
 
-    /// Borrows the cell immutably.
-    /// Panics if the cell is already borrowed mutably.
-    pub fn borrow(&self) -> OwnedRef<T> {
-        self.try_borrow()
-            .expect("Failed to borrow: already mutably borrowed")
+    drop(held);
+    if cell.borrow_stats() != (0, false) {
+        return Err(ModelViolation {
+            step: ops.len(),
+            message: format!("cell is still borrowed: {}", cell.dump_state()),
+        });
     }
+    Ok(steps)
+}
 
-    /// Borrows the cell mutably.
-    /// Panics if the cell is already borrowed immutably or mutably.
-    pub fn borrow_mut(&self) -> OwnedRefMut<T> {
-        self.try_borrow_mut()
-            .expect("Failed to borrow mutably: already borrowed")
+/// A cell with outstanding references, as returned by `report_leaked_borrows`.
+#[cfg(feature = "leak-detect")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeakReport {
+    /// The name the cell was constructed with, see `OwnedRefCell::named`.
+    pub name: &'static str,
+    /// The number of live immutable borrows of the cell.
+    pub readers: usize,
+    /// Whether the cell is borrowed mutably.
+    pub writing: bool,
+    /// Whether the cell itself has already been dropped, leaving its references behind.
+    pub cell_dropped: bool,
+}
+
+/// The name and borrow state of a cell constructed with `OwnedRefCell::named`.
+#[cfg(feature = "leak-detect")]
+type NamedCell = (&'static str, Weak<BorrowState>);
+
+#[cfg(feature = "leak-detect")]
+thread_local! {
+    /// Cells constructed with `OwnedRefCell::named` on this thread.
+    static NAMED_CELLS: RefCell<Vec<NamedCell>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Returns a report for every cell constructed with `OwnedRefCell::named` on this thread that
+/// still has a live `OwnedRef` or `OwnedRefMut`, in the order the cells were constructed. Calling
+/// this at shutdown shows which cells were left borrowed, and by what kind of reference.
+///
+/// A cell stays in the registry until it and all of its references are gone, so this also
+/// reports references that outlived their cell. Only available with the `leak-detect` feature.
+///
+/// ```
+/// use owned_ref_cell::{report_leaked_borrows, OwnedRefCell};
+///
+/// let config = OwnedRefCell::named("config", 1);
+/// let _stuck = config.borrow();
+/// let report = report_leaked_borrows();
+/// assert_eq!(report.len(), 1);
+/// assert_eq!((report[0].name, report[0].readers), ("config", 1));
+/// ```
+#[cfg(feature = "leak-detect")]
+pub fn report_leaked_borrows() -> Vec<LeakReport> {
+    NAMED_CELLS.with(|cells| {
+        let mut cells = cells.borrow_mut();
+        cells.retain(|(_, state)| state.strong_count() > 0);
+        cells
+            .iter()
+            .filter_map(|(name, state)| {
+                let state = state.upgrade()?;
+                state.is_borrowed().then(|| LeakReport {
+                    name,
+                    readers: state.reading_count.get(),
+                    writing: state.is_writing.get(),
+                    cell_dropped: state.cell_dropped.get(),
+                })
+            })
+            .collect()
+    })
+}
+
+/// Aborts the process if dropped, which only happens while unwinding out of code that must not
+/// panic; it is forgotten once that code has completed.
+struct AbortOnUnwind;
+
+impl Drop for AbortOnUnwind {
+    fn drop(&mut self) {
+        #[cfg(feature = "std")]
+        std::process::abort();
+        // Without `std` there is no `abort`, but panicking while already unwinding aborts too.
+        #[cfg(not(feature = "std"))]
+        panic!("aborting: panicked while a value was moved out of its cell");
     }
+}
 
-    /// Tries to immutably borrow the cell.
-    /// Returns `None` if the cell is already borrowed mutably.
-    pub fn try_borrow(&self) -> Option<OwnedRef<T>> {
-        let mut state = self.state.borrow_mut();
-        if state.is_writing {
-            None
+impl BorrowState {
+    /// Returns a fresh id for a newly issued guard.
+    #[cfg(feature = "diagnostics")]
+    fn next_guard_id(&self) -> u64 {
+        self.last_guard_id.set(self.last_guard_id.get() + 1);
+        self.last_guard_id.get()
+    }
+
+    /// Returns whether the cell is borrowed, either immutably or mutably.
+    fn is_borrowed(&self) -> bool {
+        self.is_writing.get() || self.reading_count.get() > 0
+    }
+
+    /// Releases one write guard's share of the write borrow, clearing the write flag once the
+    /// last one is gone.
+    fn release_write(&self) {
+        self.writing_count.set(self.writing_count.get() - 1);
+        if self.writing_count.get() == 0 {
+            self.is_writing.set(false);
+            self.version.set(self.version.get() + 1);
+            #[cfg(feature = "diagnostics")]
+            self.writer_location.set(None);
+            #[cfg(feature = "log")]
+            if let Some((tag, since)) = self.logged_write.take() {
+                log::debug!("released mut borrow {} after {:?}", tag, since.elapsed());
+            }
+        }
+        #[cfg(any(test, feature = "test-util"))]
+        self.ops.borrow_mut().push(BorrowOp::ReleaseMut);
+        self.assert_consistent();
+    }
+
+    /// Takes the registered wakers if the cell has become free, so that the caller can wake
+    /// them once it has released the state.
+    #[cfg(feature = "async")]
+    fn take_wakers_if_free(&self) -> Vec<Waker> {
+        if self.is_borrowed() {
+            Vec::new()
         } else {
-            state.reading_count += 1;
-            Some(OwnedRef {
-                value: self.value.get(),
-                state: Rc::clone(&self.state),
-            })
+            self.wakers.take()
         }
     }
 
-    /// Tries to mutably borrow the cell.
-    /// Returns `None` if the cell is already borrowed immutably or mutably.
-    pub fn try_borrow_mut(&self) -> Option<OwnedRefMut<T>> {
-        let mut state = self.state.borrow_mut();
-        if state.is_writing || state.reading_count > 0 {
+    /// Takes the callback registered by `borrow_mut_with_callback` once the write borrow has been
+    /// released by every reference sharing it, so that the caller can run it.
+    #[cfg(feature = "callbacks")]
+    fn take_write_callback_if_released(&self) -> Option<Box<dyn FnOnce()>> {
+        if self.is_writing.get() {
             None
         } else {
-            state.is_writing = true;
-            Some(OwnedRefMut {
-                value: self.value.get(),
-                state: Rc::clone(&self.state),
-            })
+            self.write_callback.take()
         }
     }
-}
 
-/// Implements `Deref` for `OwnedRef` to allow dereferencing the owned reference.
-impl<T> Deref for OwnedRef<T> {
-    type Target = T;
-
-    fn deref(&self) -> &Self::Target {
-        unsafe { self.value.as_ref().unwrap() }
+    /// Accounts for a reference to a component of the value having been released. If it was the
+    /// last reference of a cell that was dropped while borrowed, it stays counted, so that the
+    /// orphaned value is leaked when the state is dropped along with the reference.
+    fn release_projection(&self) {
+        if !self.cell_dropped.get() || self.is_borrowed() {
+            self.projected.set(self.projected.get() - 1);
+        }
     }
-}
-
-/// Implements `Deref` for `OwnedRefMut` to allow dereferencing the owned mutable reference.
-impl<T> Deref for OwnedRefMut<T> {
-    type Target = T;
 
-    fn deref(&self) -> &Self::Target {
-        unsafe { self.value.as_ref().unwrap() }
+    /// Releases the read borrow a fork holds on the cell it was made from once the fork has its
+    /// own copy of the value and no reference to the shared one can be left.
+    fn release_fork_source_if_detached(&self) {
+        if self.fork_detached.get() && !self.is_borrowed() {
+            drop(self.fork_source.take());
+        }
     }
-}
 
-/// Implements `DerefMut` for `OwnedRefMut` to allow dereferencing the owned mutable reference.
-impl<T> DerefMut for OwnedRefMut<T> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { self.value.as_mut().unwrap() }
+    /// Checks the invariants of the borrow state in debug builds. This runs after every
+    /// transition, so an accounting bug panics where it happens rather than much later.
+    ///
+    /// Readers and a writer can only coexist when part of a shared write borrow, such as one
+    /// half of `split_at_mut`, has been turned into an `OwnedRef`.
+    fn assert_consistent(&self) {
+        debug_assert_eq!(
+            self.is_writing.get(),
+            self.writing_count.get() > 0,
+            "write flag out of sync with writing_count"
+        );
+        #[cfg(feature = "diagnostics")]
+        debug_assert!(
+            self.is_writing.get() || self.writer_location.get().is_none(),
+            "writer location recorded without a write borrow"
+        );
+        #[cfg(feature = "log")]
+        debug_assert!(
+            self.is_writing.get() || self.logged_write.get().is_none(),
+            "logged write recorded without a write borrow"
+        );
     }
 }
 
-/// Implements `Drop` for `OwnedRef` and `OwnedRefMut` to update the borrowing state when the
-/// references are dropped.
-impl<T> Drop for OwnedRef<T> {
-    fn drop(&mut self) {
-        let mut state = self.state.borrow_mut();
-        state.reading_count -= 1;
+impl<T> OwnedRefCell<T> {
+    /// Constructs a new `OwnedRefCell` with the specified value.
+    pub fn new(value: T) -> Self {
+        OwnedRefCell::from_box(Box::new(value))
     }
-}
 
-/// Implements `Drop` for `OwnedRefMut` to update the borrowing state when the reference is dropped.
-impl<T> Drop for OwnedRefMut<T> {
-    fn drop(&mut self) {
-        let mut state = self.state.borrow_mut();
-        state.is_writing = false;
+    /// Constructs a new `OwnedRefCell` with the specified value and a name to identify it by in
+    /// `report_leaked_borrows`.
+    ///
+    /// Cells are only registered with the `leak-detect` feature; without it this is the same as
+    /// `new`, so call sites do not need to be feature-gated.
+    pub fn named(name: &'static str, value: T) -> Self {
+        let cell = OwnedRefCell::new(value);
+        #[cfg(feature = "leak-detect")]
+        NAMED_CELLS.with(|cells| {
+            let mut cells = cells.borrow_mut();
+            cells.retain(|(_, state)| state.strong_count() > 0);
+            cells.push((name, Rc::downgrade(&cell.state)));
+        });
+        #[cfg(not(feature = "leak-detect"))]
+        let _ = name;
+        cell
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::{
-        collections::HashMap,
-        panic::{self, AssertUnwindSafe},
-    };
+    /// Consumes the cell and returns the wrapped value.
+    /// Panics if an `OwnedRef` or `OwnedRefMut` of the cell is still alive, since it would be left
+    /// pointing at a moved value; use `try_into_inner` to get the cell back instead.
+    pub fn into_inner(self) -> T {
+        match self.try_into_inner() {
+            Ok(value) => value,
+            Err(_) => panic!("Failed to move out of the cell: still borrowed"),
+        }
+    }
 
-    use super::*;
+    /// Consumes the cell and returns the wrapped value if no `OwnedRef` or `OwnedRefMut` is
+    /// still alive. Otherwise the cell is handed back unchanged.
+    ///
+    /// Guards are not tied to the lifetime of the cell, so consuming it while one is outstanding
+    /// would leave that guard pointing at a moved value. This method checks the borrow state
+    /// first, making it the safe way to recover the value when guards may still exist.
+    pub fn try_into_inner(self) -> Result<T, Self> {
+        if self.state.is_borrowed() {
+            return Err(self);
+        }
+        self.unshare();
+        self.state.cell_dropped.set(true);
 
-    #[test]
-    fn hashmap_borrow_mut_modify_descope_borrow() {
-        let shared_map = OwnedRefCell::new(HashMap::new());
+        // The cell is being taken apart, so its `Drop` impl must not run; its fields are moved
+        // out instead.
+        let this = ManuallyDrop::new(self);
+        drop(unsafe { ptr::read(&this.state) });
+        let value = unsafe { Box::from_raw(this.value.get().as_ptr()) };
+        Ok(*value)
+    }
 
-        {
-            let mut map = shared_map.borrow_mut();
-            map.insert("a", 1);
-            map.insert("b", 2);
-            map.insert("c", 3);
-            map.insert("d", 4);
+    /// Replaces the value with `new` if it is equal to `expected`, under a single mutable borrow.
+    /// Returns the previous value on success, or hands `new` back if the values did not match.
+    /// Panics if the cell is already borrowed immutably or mutably.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn compare_and_set(&self, expected: &T, new: T) -> Result<T, T>
+    where
+        T: PartialEq,
+    {
+        let mut value = self.borrow_mut();
+        if *value == *expected {
+            Ok(mem::replace(&mut *value, new))
+        } else {
+            Err(new)
         }
+    }
 
-        let total: i32 = shared_map.borrow().values().sum();
-        assert_eq!(total, 10);
+    /// Replaces the value with `t` and returns the previous value, like `RefCell::replace`.
+    /// Panics if the cell is already borrowed immutably or mutably.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn replace(&self, t: T) -> T {
+        mem::replace(&mut *self.borrow_mut(), t)
+    }
 
-        let map_ref = {
-            let mut map = shared_map.borrow_mut();
-            map.insert("e", 5);
-            map
-        };
-        let total: i32 = map_ref.values().sum();
-        assert_eq!(total, 15);
+    /// Replaces the value with the one computed by `f` from a mutable reference to the current
+    /// value, and returns the previous value, like `RefCell::replace_with`.
+    /// Panics if the cell is already borrowed immutably or mutably.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn replace_with<F: FnOnce(&mut T) -> T>(&self, f: F) -> T {
+        let mut value = self.borrow_mut();
+        let new = f(&mut value);
+        mem::replace(&mut *value, new)
     }
 
-    #[test]
-    fn extend_borrow_across_scopes() {
-        let cell = OwnedRefCell::new(10);
+    /// Takes the value, leaving `T::default()` in its place, like `RefCell::take`.
+    /// Panics if the cell is already borrowed immutably or mutably.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn take(&self) -> T
+    where
+        T: Default,
+    {
+        self.replace(T::default())
+    }
 
-        // Function that extends the mutable borrow across its original scope
-        fn extend_borrow<T>(mut borrow: OwnedRefMut<T>, modifier: T) -> OwnedRefMut<T>
-        where
-            T: std::ops::AddAssign,
-        {
-            *borrow += modifier;
-            borrow
+    /// Tries to replace the value with `t`, returning the previous value.
+    /// Returns `t` back together with an error if the cell is already borrowed immutably or
+    /// mutably, so the new value is never lost.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn try_replace(&self, t: T) -> Result<T, (T, BorrowMutError)> {
+        match self.try_borrow_mut() {
+            Some(mut value) => Ok(mem::replace(&mut *value, t)),
+            None => Err((t, BorrowMutError { _private: () })),
         }
+    }
 
-        let borrow_mut = cell.borrow_mut();
-        let extended_borrow = extend_borrow(borrow_mut, 5);
+    /// Replaces the value with `value` and returns the previous one if the cell is not borrowed
+    /// at all. Otherwise `value` is handed back; this never panics.
+    ///
+    /// This suits a producer that swaps in new state whenever nobody is looking and retries
+    /// later otherwise.
+    pub fn publish(&self, value: T) -> Result<T, T> {
+        self.try_replace(value).map_err(|(value, _)| value)
+    }
 
-        assert_eq!(*extended_borrow, 15);
+    /// Replaces the value with `value` and returns the previous value together with an
+    /// immutable reference to the new one.
+    /// Panics if the cell is already borrowed immutably or mutably.
+    ///
+    /// The write borrow used for the replacement is turned into the returned read borrow in a
+    /// single step, so no other writer can change the new value before it is read.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn replace_and_read(&self, value: T) -> (T, OwnedRef<T>) {
+        let mut guard = self.borrow_mut();
+        let old = mem::replace(&mut *guard, value);
+        (old, OwnedRef::from(guard))
     }
 
-    #[test]
-    fn borrow_mut_modify_and_borrow_after_drop() {
-        let cell = OwnedRefCell::new(10);
-        {
-            let mut b = cell.borrow_mut();
-            *b = 20;
+    /// Temporarily replaces the value with `temp` while `f` runs, then puts the original value
+    /// back, even if `f` panics. Returns the result of `f`.
+    ///
+    /// The cell is only borrowed mutably for the two replacements, not while `f` runs, so `f`
+    /// can borrow the cell itself. Panics if the cell is borrowed when the value is swapped in,
+    /// or if `f` leaves a borrow of the cell alive when the original value is restored.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn with_replaced<R, F: FnOnce() -> R>(&self, temp: T, f: F) -> R {
+        struct Restore<'a, T> {
+            cell: &'a OwnedRefCell<T>,
+            original: Option<T>,
         }
-        let b = cell.borrow();
-        assert_eq!(*b, 20);
-    }
 
-    #[test]
-    fn cannot_borrow_mut_while_immutably_borrowed() {
-        let cell = OwnedRefCell::new(10);
-        let _b = cell.borrow();
-        assert!(cell.try_borrow_mut().is_none());
-    }
+        impl<T> Drop for Restore<'_, T> {
+            fn drop(&mut self) {
+                if let Some(original) = self.original.take() {
+                    *self.cell.borrow_mut() = original;
+                }
+            }
+        }
 
-    #[test]
-    fn cannot_borrow_while_mutably_borrowed() {
-        let cell = OwnedRefCell::new(10);
-        let _b = cell.borrow_mut();
-        assert!(cell.try_borrow().is_none());
+        let original = mem::replace(&mut *self.borrow_mut(), temp);
+        let _restore = Restore {
+            cell: self,
+            original: Some(original),
+        };
+        f()
     }
 
-    #[test]
-    fn cannot_borrow_mut_while_mutably_borrowed() {
-        let cell = OwnedRefCell::new(10);
-        let _b = cell.borrow_mut();
-        assert!(cell.try_borrow_mut().is_none());
+    /// Replaces the value with `placeholder` and returns the previous value together with a
+    /// mutable reference to the placeholder, so that it can be filled in under the same write
+    /// borrow. Other code never sees the cell without a valid value.
+    /// Panics if the cell is already borrowed immutably or mutably.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn take_with_placeholder(&self, placeholder: T) -> (T, OwnedRefMut<T>) {
+        let mut guard = self.borrow_mut();
+        let old = mem::replace(&mut *guard, placeholder);
+        (old, guard)
     }
 
-    #[test]
-    fn multiple_immutable_borrows() {
-        let cell = OwnedRefCell::new(10);
-        let b1 = cell.try_borrow().unwrap();
-        let b2 = cell.try_borrow().unwrap();
-        assert_eq!(*b1, 10);
-        assert_eq!(*b2, 10);
+    /// Swaps the values of two cells.
+    /// Panics if either cell is already borrowed immutably or mutably.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn swap(&self, other: &OwnedRefCell<T>) {
+        self.try_swap(other)
+            .expect("Failed to swap: already borrowed")
     }
 
-    #[test]
-    fn multiple_immutable_borrows_after_borrow_mut() {
-        let cell = OwnedRefCell::new(10);
-        {
-            let _b1 = cell.borrow_mut();
+    /// Tries to swap the values of two cells.
+    /// Returns an error if either cell is already borrowed immutably or mutably, in which case
+    /// neither value is touched. Swapping a cell with itself is a no-op.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn try_swap(&self, other: &OwnedRefCell<T>) -> Result<(), BorrowMutError> {
+        if ptr::eq(self, other) {
+            return Ok(());
         }
-        let b2 = cell.borrow();
-        let b3 = cell.borrow();
-        assert_eq!(*b2, 10);
-        assert_eq!(*b3, 10);
+        let mut this = self
+            .try_borrow_mut()
+            .ok_or(BorrowMutError { _private: () })?;
+        let mut other = other
+            .try_borrow_mut()
+            .ok_or(BorrowMutError { _private: () })?;
+        mem::swap(&mut *this, &mut *other);
+        Ok(())
     }
 
-    #[test]
-    fn borrow_mut_again_after_drop() {
-        let cell = OwnedRefCell::new(10);
-        {
-            let mut b = cell.borrow_mut();
-            *b = 20;
+    /// Replaces the values of two cells with the pair computed by `f` from their current values,
+    /// under a mutable borrow of both cells. `f` receives `(self, other)` and returns the new
+    /// `(self, other)` values.
+    /// Panics if either cell is already borrowed immutably or mutably.
+    ///
+    /// Like swapping a cell with itself, calling this with `other` being the same cell as `self`
+    /// is a no-op: there are not two values to pass to `f`, so it is not called.
+    ///
+    /// Both values are moved out of the cells while `f` runs, so if `f` panics there is nothing
+    /// valid left to put back and the process is aborted instead of unwinding.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn swap_map<F: FnOnce(T, T) -> (T, T)>(&self, other: &OwnedRefCell<T>, f: F) {
+        if ptr::eq(self, other) {
+            return;
         }
-        {
-            let mut b = cell.borrow_mut();
-            *b = 30;
+        let this = self.borrow_mut();
+        let other = other.borrow_mut();
+        let bomb = AbortOnUnwind;
+        unsafe {
+            let (a, b) = f(ptr::read(this.value), ptr::read(other.value));
+            ptr::write(this.value, a);
+            ptr::write(other.value, b);
         }
-        let b = cell.borrow();
-        assert_eq!(*b, 30);
+        mem::forget(bomb);
     }
 
-    #[test]
+    /// Replaces the value with the one `f` computes from it, under a single mutable borrow. This
+    /// suits state machines whose transitions consume the current state, such as moving an enum
+    /// to another variant while keeping the data of the old one.
+    /// Panics if the cell is already borrowed immutably or mutably.
+    ///
+    /// The value is moved out of the cell while `f` runs, so if `f` panics there is nothing
+    /// valid left to put back and the process is aborted instead of unwinding.
+    ///
+    /// ```
+    /// use owned_ref_cell::OwnedRefCell;
+    ///
+    /// enum Conn {
+    ///     Idle,
+    ///     Open(String),
+    /// }
+    ///
+    /// let conn = OwnedRefCell::new(Conn::Open(String::from("db")));
+    /// conn.transform(|conn| match conn {
+    ///     Conn::Open(_) => Conn::Idle,
+    ///     Conn::Idle => Conn::Open(String::from("db")),
+    /// });
+    /// assert!(matches!(*conn.borrow(), Conn::Idle));
+    /// ```
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn transform<F: FnOnce(T) -> T>(&self, f: F) {
+        let this = self.borrow_mut();
+        let bomb = AbortOnUnwind;
+        unsafe {
+            let value = f(ptr::read(this.value));
+            ptr::write(this.value, value);
+        }
+        mem::forget(bomb);
+    }
+
+    /// Returns a clone of the value, or `T::default()` if the cell is currently borrowed mutably.
+    /// This never panics.
+    ///
+    /// This trades correctness for liveness: while a writer holds the cell, the caller silently
+    /// gets a default value instead of the real one. Only use it where a stale or default read
+    /// is acceptable, such as best-effort display code.
+    pub fn borrow_or_default(&self) -> T
+    where
+        T: Default + Clone,
+    {
+        match self.try_borrow() {
+            Some(value) => T::clone(&value),
+            None => T::default(),
+        }
+    }
+
+    /// Creates a cell with its own fresh borrow state that shares the current value until it is
+    /// first written to, and only then makes its own copy of it with `T::clone`.
+    /// Panics if the cell is currently borrowed mutably.
+    ///
+    /// Until then, the fork reads the value through an `OwnedRef` to this cell, so this cell
+    /// cannot be borrowed mutably while the fork shares its value. The copy is made on the first
+    /// `borrow_mut` of the fork, or whenever it needs a value of its own, such as in `get_mut`,
+    /// `as_ptr` or `downgrade`, and the shared borrow is released once no reference of the fork
+    /// to the shared value is left. Dropping a fork that was never written to copies nothing.
+    /// Like the rest of the cell, this is single-threaded.
+    ///
+    /// ```
+    /// use owned_ref_cell::OwnedRefCell;
+    ///
+    /// let cell = OwnedRefCell::new(vec![1, 2]);
+    /// let fork = cell.fork();
+    /// assert!(cell.try_borrow_mut().is_none());
+    ///
+    /// fork.borrow_mut().push(3);
+    /// cell.borrow_mut().push(4);
+    /// assert_eq!(*cell.borrow(), [1, 2, 4]);
+    /// assert_eq!(*fork.borrow(), [1, 2, 3]);
+    /// ```
+    pub fn fork(&self) -> OwnedRefCell<T>
+    where
+        T: Clone,
+    {
+        unsafe fn drop_guard<T>(guard: *mut ()) {
+            drop(Box::from_raw(guard as *mut OwnedRef<T>));
+        }
+
+        let guard = self.borrow();
+        let value = NonNull::from(&*guard);
+        let state = BorrowState::default();
+        state.fork_source.set(Some(ForkSource {
+            guard: Box::into_raw(Box::new(guard)) as *mut (),
+            drop: drop_guard::<T>,
+        }));
+        OwnedRefCell {
+            state: Rc::new(state),
+            value: Cell::new(value),
+            fork_clone: Cell::new(Some(|value| Box::new(T::clone(value)))),
+        }
+    }
+
+    /// Returns a snapshot holding a clone of the current value, without keeping the cell
+    /// borrowed. Panics if the cell is currently borrowed mutably.
+    pub fn snapshot(&self) -> Snapshot<T>
+    where
+        T: Clone,
+    {
+        Snapshot {
+            value: T::clone(&self.borrow()),
+        }
+    }
+
+    /// Borrows the cell immutably and reinterprets the value as a `U`, for example to read a
+    /// `[u8; N]` cell as a plain-old-data struct. The read borrow is held for as long as the
+    /// returned reference is alive.
+    /// Panics if the cell is already borrowed mutably, or if the size or alignment of the value
+    /// does not fit `U`, following `bytemuck::from_bytes`.
+    ///
+    /// Only available with the `bytemuck` feature.
+    #[cfg(feature = "bytemuck")]
+    pub fn borrow_as<U: bytemuck::Pod>(&self) -> OwnedRef<U>
+    where
+        T: bytemuck::Pod,
+    {
+        let guard = self.borrow();
+        let value: *const U = bytemuck::from_bytes(bytemuck::bytes_of(&*guard));
+        OwnedRef::project(guard, value)
+    }
+
+    /// Borrows the cell mutably for an all-or-nothing edit. The value is cloned up front, and
+    /// the clone is restored when the returned guard is dropped, including by a panic, unless
+    /// `TxnGuard::commit` was called first.
+    /// Panics if the cell is already borrowed immutably or mutably.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn begin_mut(&self) -> TxnGuard<T>
+    where
+        T: Clone,
+    {
+        let guard = self.borrow_mut();
+        let snapshot = Some((*guard).clone());
+        TxnGuard { guard, snapshot }
+    }
+
+    /// Replaces the value with a clone of the value in `other`.
+    /// Panics if `other` is already borrowed mutably or `self` is already borrowed immutably or
+    /// mutably. Overwriting a cell from itself is a no-op.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn overwrite_from(&self, other: &OwnedRefCell<T>)
+    where
+        T: Clone,
+    {
+        if ptr::eq(self, other) {
+            return;
+        }
+        let value = T::clone(&other.borrow());
+        *self.borrow_mut() = value;
+    }
+}
+
+impl<T: ?Sized> OwnedRefCell<T> {
+    /// Constructs a new `OwnedRefCell` that takes over the boxed value, which may be unsized.
+    ///
+    /// This is the way to make a cell of a trait object or slice: an `OwnedRefCell<Concrete>`
+    /// does not coerce to an `OwnedRefCell<dyn Trait>` itself, since implementing the
+    /// `CoerceUnsized` trait that allows it needs a nightly compiler, so the `Box` is coerced
+    /// instead.
+    ///
+    /// ```
+    /// use owned_ref_cell::OwnedRefCell;
+    /// use std::fmt::Display;
+    ///
+    /// let cells: Vec<OwnedRefCell<dyn Display>> = vec![
+    ///     OwnedRefCell::from_box(Box::new(1)),
+    ///     OwnedRefCell::from_box(Box::new("two")),
+    /// ];
+    /// let rendered: Vec<String> = cells.iter().map(|cell| cell.borrow().to_string()).collect();
+    /// assert_eq!(rendered, ["1", "two"]);
+    /// ```
+    pub fn from_box(value: Box<T>) -> Self {
+        OwnedRefCell {
+            state: Rc::new(BorrowState::default()),
+            value: Cell::new(unsafe { NonNull::new_unchecked(Box::into_raw(value)) }),
+            fork_clone: Cell::new(None),
+        }
+    }
+
+    /// Moves the cell into an `Rc`, which is the recommended way to share it.
+    ///
+    /// References keep the value alive on their own, but borrowing again needs the cell. Keeping
+    /// it in an `Rc` and handing a clone of the `Rc` to whoever holds a reference lets them take
+    /// new references later on:
+    ///
+    /// ```
+    /// use owned_ref_cell::{OwnedRef, OwnedRefCell};
+    /// use std::rc::Rc;
+    ///
+    /// // Fields are dropped in order, so the reference goes before the cell.
+    /// struct Reader {
+    ///     values: OwnedRef<Vec<i32>>,
+    ///     _cell: Rc<OwnedRefCell<Vec<i32>>>,
+    /// }
+    ///
+    /// let cell = OwnedRefCell::new(vec![1, 2, 3]).into_shared();
+    /// let reader = Reader {
+    ///     values: cell.borrow(),
+    ///     _cell: Rc::clone(&cell),
+    /// };
+    /// drop(cell);
+    /// assert_eq!(reader.values.len(), 3);
+    /// ```
+    pub fn into_shared(self) -> Rc<OwnedRefCell<T>> {
+        Rc::new(self)
+    }
+
+    /// Borrows the cell immutably.
+    /// Panics if the cell is already borrowed mutably. The panic message names the location of
+    /// the call, and with the `diagnostics` feature also where the mutable borrow was taken.
+    #[track_caller]
+    pub fn borrow(&self) -> OwnedRef<T> {
+        match self.try_borrow() {
+            Some(guard) => guard,
+            None => self.borrow_failed("Failed to borrow: already mutably borrowed"),
+        }
+    }
+
+    /// Borrows the cell mutably.
+    /// Panics if the cell is already borrowed immutably or mutably. The panic message names the
+    /// location of the call, and with the `diagnostics` feature also where a conflicting mutable
+    /// borrow was taken.
+    #[track_caller]
+    pub fn borrow_mut(&self) -> OwnedRefMut<T> {
+        match self.try_borrow_mut() {
+            Some(guard) => guard,
+            None => self.borrow_failed("Failed to borrow mutably: already borrowed"),
+        }
+    }
+
+    /// Panics with `message`, followed by the location of the caller and, with the
+    /// `diagnostics` feature, the location of the active mutable borrow.
+    #[cold]
+    #[track_caller]
+    fn borrow_failed(&self, message: &str) -> ! {
+        #[allow(unused_mut)]
+        let mut message = format!("{}, called at {}", message, Location::caller());
+        #[cfg(feature = "diagnostics")]
+        if let Some(location) = self.state.writer_location.get() {
+            message.push_str(&format!(", mutable borrow taken at {}", location));
+        }
+        panic!("{}", message)
+    }
+
+    /// Returns a mutable reference to the value through exclusive access to the cell, without
+    /// taking a borrow, like `RefCell::get_mut`.
+    /// Panics if the cell is already borrowed immutably or mutably.
+    ///
+    /// Unlike `RefCell`'s guards, an `OwnedRef` or `OwnedRefMut` does not borrow the cell it was
+    /// taken from, so `&mut self` alone does not rule out live references. The borrow state is
+    /// therefore still checked, but no borrow is taken. Like `borrow_mut`, the panic message
+    /// names where a conflicting mutable borrow was taken with the `diagnostics` feature.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn get_mut(&mut self) -> &mut T {
+        if self.state.is_borrowed() {
+            self.borrow_failed("Failed to borrow mutably: already borrowed");
+        }
+        self.unshare();
+        unsafe { self.value.get_mut().as_mut() }
+    }
+
+    /// Borrows the cell mutably and emits a `log` debug event tagged with `tag` once the write
+    /// borrow is released, reporting how long it was held.
+    /// Panics if the cell is already borrowed immutably or mutably.
+    ///
+    /// The tag and start time are kept with the borrow state rather than in the returned
+    /// reference, so a write borrow that is split or mapped is logged once, when the last
+    /// reference sharing it is released, with the time the whole borrow was held.
+    ///
+    /// This is meant for finding code that holds write borrows for too long, and is only
+    /// available with the `log` feature.
+    #[cfg(feature = "log")]
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn borrow_mut_logged(&self, tag: &'static str) -> OwnedRefMut<T> {
+        let guard = self.borrow_mut();
+        self.state.logged_write.set(Some((tag, Instant::now())));
+        guard
+    }
+
+    /// Borrows the cell immutably and arranges for `on_release` to be called once the returned
+    /// reference, or any reference it is mapped into, is dropped.
+    /// Panics if the cell is already borrowed mutably.
+    ///
+    /// The callback runs after the read borrow has been released. If the reference is upgraded
+    /// with `OwnedRef::try_upgrade`, it runs once the resulting write borrow is released instead.
+    /// Only available with the `callbacks` feature.
+    #[cfg(feature = "callbacks")]
+    pub fn borrow_with_callback(&self, on_release: impl FnOnce() + 'static) -> OwnedRef<T> {
+        let mut guard = self.borrow();
+        guard.on_release = Some(Box::new(on_release));
+        guard
+    }
+
+    /// Borrows the cell mutably and arranges for `on_release` to be called once the write borrow
+    /// of the returned reference is released, either by dropping it, or any reference it is
+    /// mapped into, or by turning it into an `OwnedRef`. If the reference is split, the write
+    /// borrow is only released once every part has been dropped or turned into an `OwnedRef`.
+    /// Panics if the cell is already borrowed immutably or mutably.
+    ///
+    /// The callback is kept with the borrow state and runs after the write flag has been
+    /// cleared, so it can borrow the cell itself, for example to recompute something derived
+    /// from the new value. Only available with the `callbacks` feature.
+    #[cfg(feature = "callbacks")]
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn borrow_mut_with_callback(&self, on_release: impl FnOnce() + 'static) -> OwnedRefMut<T> {
+        let guard = self.borrow_mut();
+        self.state.write_callback.set(Some(Box::new(on_release)));
+        guard
+    }
+
+    /// Tries to immutably borrow the cell.
+    /// Returns `None` if the cell is already borrowed mutably, or if it already has `usize::MAX`
+    /// immutable borrows.
+    pub fn try_borrow(&self) -> Option<OwnedRef<T>> {
+        OwnedRef::acquire(self.value.get().as_ptr(), &self.state)
+    }
+
+    /// Tries to mutably borrow the cell.
+    /// Returns `None` if the cell is already borrowed immutably or mutably.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn try_borrow_mut(&self) -> Option<OwnedRefMut<T>> {
+        let state = &self.state;
+        if state.is_borrowed() {
+            None
+        } else {
+            self.unshare();
+            state.is_writing.set(true);
+            state.writing_count.set(1);
+            #[cfg(any(test, feature = "test-util"))]
+            state.ops.borrow_mut().push(BorrowOp::BorrowMut);
+            state.assert_consistent();
+            #[cfg(feature = "diagnostics")]
+            state.writer_location.set(Some(Location::caller()));
+            #[cfg(feature = "depth_tracking")]
+            enter_guard();
+            Some(OwnedRefMut {
+                value: self.value.get().as_ptr(),
+                state: Rc::clone(&self.state),
+                projected: false,
+                #[cfg(feature = "diagnostics")]
+                id: state.next_guard_id(),
+                #[cfg(feature = "recursion_detect")]
+                reentrancy_key: None,
+            })
+        }
+    }
+
+    /// Tries to immutably borrow the cell, like `try_borrow`, but reports a failure as a
+    /// `BorrowError` for use with `?`, like `RefCell::try_borrow`.
+    /// Returns an error if the cell is already borrowed mutably.
+    pub fn try_borrow_result(&self) -> Result<OwnedRef<T>, BorrowError> {
+        self.try_borrow().ok_or(BorrowError { _private: () })
+    }
+
+    /// Tries to mutably borrow the cell, like `try_borrow_mut`, but reports a failure as a
+    /// `BorrowMutError` for use with `?`, like `RefCell::try_borrow_mut`.
+    /// Returns an error if the cell is already borrowed immutably or mutably.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn try_borrow_mut_result(&self) -> Result<OwnedRefMut<T>, BorrowMutError> {
+        self.try_borrow_mut().ok_or(BorrowMutError { _private: () })
+    }
+
+    /// Borrows the cell mutably, calling `yielder` and retrying for as long as the cell is
+    /// already borrowed. `yielder` is expected to hand control back to a cooperative scheduler
+    /// so that the task holding the conflicting borrow can make progress and drop it.
+    ///
+    /// This spins forever if the conflicting borrow is never dropped; see
+    /// `try_borrow_mut_or_yield` for a bounded variant.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn borrow_mut_or_yield(&self, mut yielder: impl FnMut()) -> OwnedRefMut<T> {
+        loop {
+            if let Some(guard) = self.try_borrow_mut() {
+                return guard;
+            }
+            yielder();
+        }
+    }
+
+    /// Like `borrow_mut_or_yield`, but gives up and returns `None` after calling `yielder`
+    /// `max_yields` times without acquiring the borrow.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn try_borrow_mut_or_yield(
+        &self,
+        max_yields: usize,
+        mut yielder: impl FnMut(),
+    ) -> Option<OwnedRefMut<T>> {
+        for _ in 0..max_yields {
+            if let Some(guard) = self.try_borrow_mut() {
+                return Some(guard);
+            }
+            yielder();
+        }
+        self.try_borrow_mut()
+    }
+
+    /// Borrows `a` immutably and `b` mutably, all or nothing, for deriving the new value of `b`
+    /// from `a`. If `b` can't be borrowed, the read borrow of `a` is released again.
+    /// Returns `None` if `a` is already borrowed mutably or `b` is already borrowed immutably or
+    /// mutably, which includes passing the same cell twice.
+    ///
+    /// This is an associated function that needs to be used as
+    /// `OwnedRefCell::try_borrow_read_write(...)`.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn try_borrow_read_write<B: ?Sized>(
+        a: &OwnedRefCell<T>,
+        b: &OwnedRefCell<B>,
+    ) -> Option<(OwnedRef<T>, OwnedRefMut<B>)> {
+        let read = a.try_borrow()?;
+        let write = b.try_borrow_mut()?;
+        Some((read, write))
+    }
+
+    /// Asserts that no `OwnedRef` or `OwnedRefMut` for this cell is still alive.
+    /// Panics with a message describing the outstanding borrow otherwise.
+    ///
+    /// This is a test helper for checking that a piece of code released its borrows by a given
+    /// point, and is only available in this crate's tests or with the `test-util` feature.
+    #[cfg(any(test, feature = "test-util"))]
+    #[track_caller]
+    pub fn assert_not_borrowed(&self) {
+        if self.state.is_writing.get() {
+            panic!("OwnedRefCell is still mutably borrowed");
+        }
+        if self.state.reading_count.get() > 0 {
+            panic!(
+                "OwnedRefCell is still immutably borrowed by {} reference(s)",
+                self.state.reading_count.get()
+            );
+        }
+    }
+
+    /// Borrows the cell immutably, returning a guard tied to the lifetime of `&self` like a
+    /// `std::cell::Ref`. Panics if the cell is already borrowed mutably.
+    ///
+    /// This is useful when passing a borrow to code written against scope-bound guards. A value
+    /// that currently lives in a `RefCell` can be moved into an `OwnedRefCell` with
+    /// `OwnedRefCell::new(ref_cell.into_inner())`.
+    ///
+    /// ```
+    /// use owned_ref_cell::OwnedRefCell;
+    /// use std::cell::RefCell;
+    /// use std::ops::Deref;
+    ///
+    /// fn total(values: impl Deref<Target = Vec<i32>>) -> i32 {
+    ///     values.iter().sum()
+    /// }
+    ///
+    /// let ref_cell = RefCell::new(vec![1, 2, 3]);
+    /// let cell = OwnedRefCell::new(ref_cell.into_inner());
+    /// assert_eq!(total(cell.borrow_as_std()), 6);
+    /// ```
+    pub fn borrow_as_std(&self) -> impl Deref<Target = T> + '_ {
+        self.borrow()
+    }
+
+    /// Borrows the cell immutably, failing if a guard returned by `borrow_recursive` for this cell
+    /// is still alive on the current thread. This catches a recursive traversal re-entering the
+    /// same cell, for example when walking a graph that contains a cycle.
+    /// Panics if the cell is already borrowed mutably.
+    #[cfg(feature = "recursion_detect")]
+    pub fn borrow_recursive(&self) -> Result<OwnedRef<T>, RecursionError> {
+        let key = Rc::as_ptr(&self.state) as usize;
+        if RECURSIVE_BORROWS.with(|keys| keys.borrow().contains(&key)) {
+            return Err(RecursionError { _private: () });
+        }
+        let mut guard = self.borrow();
+        RECURSIVE_BORROWS.with(|keys| keys.borrow_mut().insert(key));
+        guard.recursion_key = Some(key);
+        Ok(guard)
+    }
+
+    /// Borrows the cell mutably, failing if a guard returned by `borrow_mut_guarded` for this
+    /// cell is still alive on the current thread. This turns a handler accidentally re-entering
+    /// a cell it already holds into a descriptive error rather than a generic borrow panic.
+    /// Panics if the cell is otherwise already borrowed immutably or mutably.
+    #[cfg(feature = "recursion_detect")]
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn borrow_mut_guarded(&self) -> Result<OwnedRefMut<T>, ReentrantBorrowError> {
+        let key = Rc::as_ptr(&self.state) as usize;
+        if GUARDED_WRITES.with(|keys| keys.borrow().contains(&key)) {
+            return Err(ReentrantBorrowError { _private: () });
+        }
+        let mut guard = self.borrow_mut();
+        GUARDED_WRITES.with(|keys| keys.borrow_mut().insert(key));
+        guard.reentrancy_key = Some(key);
+        Ok(guard)
+    }
+
+    /// Tries to borrow the cell immutably and make a reference to the component of the value
+    /// selected by `f`, like `try_borrow` followed by `OwnedRef::map`.
+    /// Returns an error if the cell is already borrowed mutably, in which case `f` is not called.
+    pub fn try_borrow_map<U: ?Sized, F: FnOnce(&T) -> &U>(
+        &self,
+        f: F,
+    ) -> Result<OwnedRef<U>, BorrowError> {
+        let guard = self.try_borrow().ok_or(BorrowError { _private: () })?;
+        Ok(OwnedRef::map(guard, f))
+    }
+
+    /// Tries to borrow the cell mutably and make a reference to the component of the value
+    /// selected by `f`, like `try_borrow_mut` followed by `OwnedRefMut::map`.
+    /// Returns an error if the cell is already borrowed immutably or mutably, in which case `f`
+    /// is not called.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn try_borrow_mut_map<U: ?Sized, F: FnOnce(&mut T) -> &mut U>(
+        &self,
+        f: F,
+    ) -> Result<OwnedRefMut<U>, BorrowMutError> {
+        let guard = self
+            .try_borrow_mut()
+            .ok_or(BorrowMutError { _private: () })?;
+        Ok(OwnedRefMut::map(guard, f))
+    }
+
+    /// Borrows the cell mutably and makes two references to the disjoint components of the
+    /// value selected by `f`. Both share the write borrow, which is only released once both of
+    /// them have been dropped.
+    /// Panics if the cell is already borrowed immutably or mutably.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn borrow_fields_mut<U: ?Sized, V: ?Sized, F>(
+        &self,
+        f: F,
+    ) -> (OwnedRefMut<U>, OwnedRefMut<V>)
+    where
+        F: FnOnce(&mut T) -> (&mut U, &mut V),
+    {
+        let mut guard = self.borrow_mut();
+        let (first, second) = f(&mut guard);
+        let (first, second) = (first as *mut U, second as *mut V);
+        let second = OwnedRefMut::share(&guard, second);
+        (OwnedRefMut::project(guard, first), second)
+    }
+
+    /// Runs `f` on a transient mutable borrow of the value and returns its result.
+    /// The borrow is released before returning. Panics if the cell is already borrowed
+    /// immutably or mutably.
+    #[inline]
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn borrow_mut_then<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> R {
+        f(&mut self.borrow_mut())
+    }
+
+    /// Runs `f` on a transient immutable borrow of the value and returns its result.
+    /// The borrow is released before returning, so it cannot be held on to by accident.
+    /// Panics if the cell is already borrowed mutably, naming the location of the call.
+    ///
+    /// ```
+    /// use owned_ref_cell::OwnedRefCell;
+    ///
+    /// let cell = OwnedRefCell::new(vec![1, 2, 3]);
+    /// assert_eq!(cell.with(|values| values.len()), 3);
+    /// assert!(!cell.is_borrowed());
+    /// ```
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn with<R, F: FnOnce(&T) -> R>(&self, f: F) -> R {
+        f(&self.borrow())
+    }
+
+    /// Runs `f` on a transient mutable borrow of the value and returns its result.
+    /// The borrow is released before returning, so it cannot be held on to by accident.
+    /// Panics if the cell is already borrowed immutably or mutably, naming the location of the
+    /// call.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn with_mut<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> R {
+        f(&mut self.borrow_mut())
+    }
+
+    /// Modifies the value in place by running `f` on a transient mutable borrow, which is
+    /// released before returning.
+    /// Panics if the cell is already borrowed immutably or mutably, naming the location of the
+    /// call.
+    ///
+    /// ```
+    /// use owned_ref_cell::OwnedRefCell;
+    ///
+    /// let cell = OwnedRefCell::new(Vec::new());
+    /// cell.update(|values| values.push(1));
+    /// assert_eq!(*cell.borrow(), [1]);
+    /// ```
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn update<F: FnOnce(&mut T)>(&self, f: F) {
+        f(&mut self.borrow_mut());
+    }
+
+    /// Runs `f` on a transient immutable borrow of the value, like `with`.
+    /// Returns `None` without running `f` if the cell is already borrowed mutably.
+    pub fn try_with<R, F: FnOnce(&T) -> R>(&self, f: F) -> Option<R> {
+        self.try_borrow().map(|value| f(&value))
+    }
+
+    /// Runs `f` on a transient mutable borrow of the value, like `with_mut`.
+    /// Returns `None` without running `f` if the cell is already borrowed immutably or mutably.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn try_with_mut<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> Option<R> {
+        self.try_borrow_mut().map(|mut value| f(&mut value))
+    }
+
+    /// Runs `f` on a transient mutable borrow of the value and returns its result, like
+    /// `borrow_mut_then`. It is named for ring-buffer style cells, where `f` writes the next
+    /// slot and returns the entry it overwrote under the same borrow.
+    /// Panics if the cell is already borrowed immutably or mutably.
+    ///
+    /// ```
+    /// use owned_ref_cell::OwnedRefCell;
+    /// use std::collections::VecDeque;
+    ///
+    /// let ring = OwnedRefCell::new(VecDeque::from([1, 2, 3]));
+    /// let evicted = ring.rotate_in(|ring| {
+    ///     let evicted = ring.pop_front();
+    ///     ring.push_back(4);
+    ///     evicted
+    /// });
+    /// assert_eq!(evicted, Some(1));
+    /// assert_eq!(*ring.borrow(), [2, 3, 4]);
+    /// ```
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn rotate_in<F: FnOnce(&mut T) -> R, R>(&self, f: F) -> R {
+        self.borrow_mut_then(f)
+    }
+
+    /// Runs the fallible `f` on a transient mutable borrow of the value and returns its result.
+    ///
+    /// The outer `Result` reports whether the cell could be borrowed: it is a `BorrowMutError` if
+    /// the cell is already borrowed immutably or mutably, in which case `f` is not called. The
+    /// inner `Result` is the one returned by `f`, so a failing handler and a failing borrow can't
+    /// be mistaken for each other. The borrow is released before returning.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn with_borrow_mut_result<R, E, F: FnOnce(&mut T) -> Result<R, E>>(
+        &self,
+        f: F,
+    ) -> Result<Result<R, E>, BorrowMutError> {
+        let mut value = self
+            .try_borrow_mut()
+            .ok_or(BorrowMutError { _private: () })?;
+        Ok(f(&mut value))
+    }
+
+    /// Borrows the cell immutably and returns a reference that dereferences to `f` applied to
+    /// the value, computed on first use. Panics if the cell is already borrowed mutably.
+    ///
+    /// ```
+    /// use owned_ref_cell::OwnedRefCell;
+    ///
+    /// let cell = OwnedRefCell::new(vec![1, 2, 3]);
+    /// let label = cell.borrow_computed(|values| format!("{} values", values.len()));
+    /// assert_eq!(*label, "3 values");
+    /// ```
+    pub fn borrow_computed<U, F: Fn(&T) -> U + 'static>(&self, f: F) -> ComputedRef<T, U> {
+        ComputedRef {
+            guard: self.borrow(),
+            compute: Box::new(f),
+            cached: OnceCell::new(),
+        }
+    }
+
+    /// Borrows the cell immutably, failing if `borrow_depth()` has already reached `max_depth`.
+    /// This turns runaway recursion in tree walks into an error before it overflows the stack.
+    /// Panics if the cell is already borrowed mutably.
+    #[cfg(feature = "depth_tracking")]
+    pub fn borrow_guarded(&self, max_depth: usize) -> Result<OwnedRef<T>, DepthLimitError> {
+        if borrow_depth() >= max_depth {
+            return Err(DepthLimitError { _private: () });
+        }
+        Ok(self.borrow())
+    }
+
+    /// Runs `f` on a transient immutable borrow of the value, for side effects only.
+    /// The borrow is released before returning. Panics if the cell is already borrowed mutably.
+    pub fn inspect<F: FnOnce(&T)>(&self, f: F) {
+        f(&self.borrow());
+    }
+
+    /// Returns `true` if `self` and `other` are the same cell, as opposed to two cells that
+    /// merely hold equal values. This never borrows either cell.
+    ///
+    /// ```
+    /// use owned_ref_cell::OwnedRefCell;
+    ///
+    /// let a = OwnedRefCell::new(1);
+    /// let b = OwnedRefCell::new(1);
+    /// assert!(a.same_cell(&a));
+    /// assert!(!a.same_cell(&b));
+    /// assert!(*a.borrow() == *b.borrow());
+    /// ```
+    pub fn same_cell(&self, other: &OwnedRefCell<T>) -> bool {
+        Rc::ptr_eq(&self.state, &other.state)
+    }
+
+    /// Returns a raw pointer to the value, like `RefCell::as_ptr`, without borrowing the cell.
+    ///
+    /// The borrow state is neither checked nor updated, so the caller is responsible for not
+    /// violating the aliasing rules through the pointer: it must not be written through while any
+    /// reference to the value is alive, nor read through while an `OwnedRefMut` is.
+    pub fn as_ptr(&self) -> *mut T {
+        self.unshare();
+        self.value.get().as_ptr()
+    }
+
+    /// Returns the version of the value, which starts at zero and is incremented every time a
+    /// write borrow of the cell is released. Comparing it with `OwnedRef::version` of an
+    /// earlier reference tells whether the value may have changed since.
+    pub fn version(&self) -> u64 {
+        self.state.version.get()
+    }
+
+    /// Makes a weak handle to the cell, which can later be upgraded to an immutable reference if
+    /// the cell is still alive.
+    pub fn downgrade(&self) -> WeakOwnedRef<T> {
+        self.unshare();
+        WeakOwnedRef {
+            value: self.value.get().as_ptr(),
+            state: Rc::downgrade(&self.state),
+        }
+    }
+
+    /// Returns the number of `WeakOwnedRef`s to this cell that are still alive.
+    pub fn weak_count(&self) -> usize {
+        Rc::weak_count(&self.state)
+    }
+
+    /// Returns whether the cell is borrowed at all, immutably or mutably, without attempting a
+    /// borrow.
+    pub fn is_borrowed(&self) -> bool {
+        self.state.is_borrowed()
+    }
+
+    /// Returns whether the cell is borrowed mutably, without attempting a borrow.
+    pub fn is_mutably_borrowed(&self) -> bool {
+        self.state.is_writing.get()
+    }
+
+    /// Returns the number of live immutable borrows of the cell, without attempting a borrow.
+    pub fn readers(&self) -> usize {
+        self.state.reading_count.get()
+    }
+
+    /// Returns the number of live immutable borrows and whether the cell is borrowed mutably,
+    /// read from the borrow state in one go.
+    pub fn borrow_stats(&self) -> (usize, bool) {
+        (self.state.reading_count.get(), self.state.is_writing.get())
+    }
+
+    /// Renders a human-readable summary of the borrow state, such as
+    /// `readers: 0, writing: true`, for logging from a panic hook. With the `diagnostics`
+    /// feature it also includes where the active mutable borrow was taken and the id of the
+    /// most recently issued reference.
+    pub fn dump_state(&self) -> String {
+        let state = &self.state;
+        #[allow(unused_mut)]
+        let mut dump = format!(
+            "readers: {}, writing: {}",
+            state.reading_count.get(),
+            state.is_writing.get()
+        );
+        #[cfg(feature = "diagnostics")]
+        {
+            if let Some(location) = state.writer_location.get() {
+                dump.push_str(&format!(", writer taken at {}", location));
+            }
+            dump.push_str(&format!(", last guard id: {}", state.last_guard_id.get()));
+        }
+        dump
+    }
+
+    /// Returns every transition of the borrow state of this cell so far, oldest first, for
+    /// asserting on the order of borrows and releases.
+    ///
+    /// This is a test helper, and is only available in this crate's tests or with the
+    /// `test-util` feature.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn borrow_ops(&self) -> Vec<BorrowOp> {
+        self.state.ops.borrow().clone()
+    }
+
+    /// Checks the internal invariants of the borrow state, panicking if they are violated.
+    /// Only does anything in debug builds.
+    #[cfg(test)]
+    fn assert_consistent(&self) {
+        self.state.assert_consistent();
+    }
+
+    /// Makes the copy of the value of a fork that still shares it with the cell it was made from,
+    /// see `fork`. Must not be called while the cell is borrowed mutably.
+    fn unshare(&self) {
+        if let Some(clone) = self.fork_clone.get() {
+            let value = clone(unsafe { self.value.get().as_ref() });
+            self.value
+                .set(unsafe { NonNull::new_unchecked(Box::into_raw(value)) });
+            self.fork_clone.set(None);
+            self.state.fork_detached.set(true);
+            self.state.release_fork_source_if_detached();
+        }
+    }
+
+    /// Registers `waker` to be woken the next time the cell becomes free, when its last
+    /// `OwnedRef` or `OwnedRefMut` is dropped. A waker that `Waker::will_wake` reports as
+    /// equivalent to an already registered one is skipped, but as that check is best-effort,
+    /// a task may still be woken more than once.
+    ///
+    /// The cell is not `Sync`, so this only works with single-threaded executors, and is only
+    /// available with the `async` feature.
+    #[cfg(feature = "async")]
+    pub fn register_waker(&self, waker: &Waker) {
+        let mut wakers = self.state.wakers.borrow_mut();
+        if !wakers.iter().any(|registered| registered.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+
+    /// Returns a future that borrows the cell mutably as soon as it is free, turning it into a
+    /// cooperative lock for tasks on a single-threaded executor.
+    ///
+    /// Only available with the `async` feature.
+    #[cfg(feature = "async")]
+    pub fn borrow_mut_async(&self) -> BorrowMutFuture<'_, T> {
+        BorrowMutFuture { cell: self }
+    }
+
+    /// Returns the location where the currently active mutable borrow was taken, without
+    /// attempting a borrow. Returns `None` if the cell is not mutably borrowed, or if the
+    /// `diagnostics` feature is disabled.
+    pub fn current_writer_location(&self) -> Option<&'static Location<'static>> {
+        #[cfg(feature = "diagnostics")]
+        {
+            self.state.writer_location.get()
+        }
+        #[cfg(not(feature = "diagnostics"))]
+        {
+            None
+        }
+    }
+}
+
+impl<T> OwnedRefCell<Vec<T>> {
+    /// Retains only the elements for which `f` returns `true`, under a single mutable borrow.
+    /// Panics if the cell is already borrowed immutably or mutably.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn retain<F: FnMut(&T) -> bool>(&self, f: F) {
+        self.borrow_mut().retain(f)
+    }
+
+    /// Applies `f` to every element, under a single mutable borrow.
+    /// Panics if the cell is already borrowed immutably or mutably.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn for_each_mut<F: FnMut(&mut T)>(&self, f: F) {
+        self.borrow_mut().iter_mut().for_each(f)
+    }
+
+    /// Appends `value` to the back of the vector, under a single mutable borrow.
+    /// Panics if the cell is already borrowed immutably or mutably.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn push(&self, value: T) {
+        self.borrow_mut().push(value)
+    }
+
+    /// Removes the last element of the vector and returns it, or `None` if it is empty.
+    /// Panics if the cell is already borrowed immutably or mutably.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn pop(&self) -> Option<T> {
+        self.borrow_mut().pop()
+    }
+
+    /// Removes the element at `index` and returns it, shifting all elements after it to the left.
+    /// Panics if `index` is out of bounds, or if the cell is already borrowed immutably or
+    /// mutably.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn remove(&self, index: usize) -> T {
+        self.borrow_mut().remove(index)
+    }
+
+    /// Removes the element at `index` and returns it, replacing it with the last element.
+    /// Panics if `index` is out of bounds, or if the cell is already borrowed immutably or
+    /// mutably.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn swap_remove(&self, index: usize) -> T {
+        self.borrow_mut().swap_remove(index)
+    }
+
+    /// Takes all elements out of the vector, leaving it empty, and returns them.
+    /// Panics if the cell is already borrowed immutably or mutably.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn drain_collect(&self) -> Vec<T> {
+        mem::take(&mut *self.borrow_mut())
+    }
+
+    /// Mutably borrows the elements in `range`, keeping the write borrow of the whole vector.
+    /// Returns `None` if `range` is out of bounds for the current length, or if the cell is
+    /// already borrowed immutably or mutably.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn borrow_range_mut(&self, range: Range<usize>) -> Option<OwnedRefMut<[T]>> {
+        let mut values = self.try_borrow_mut()?;
+        let window: *mut [T] = values.get_mut(range)?;
+        Some(OwnedRefMut::project(values, window))
+    }
+
+    /// Mutably borrows two distinct elements of the vector at once, like
+    /// `<[T]>::get_disjoint_mut`. Both references share one write borrow of the cell, which is
+    /// only released once both of them have been dropped.
+    /// Returns `None` if the indices are equal or out of bounds, or if the cell is already
+    /// borrowed immutably or mutably.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn get_disjoint_mut(&self, indices: [usize; 2]) -> Option<[OwnedRefMut<T>; 2]> {
+        let [i, j] = indices;
+        let mut values = self.try_borrow_mut()?;
+        if i == j || i >= values.len() || j >= values.len() {
+            return None;
+        }
+        let base = values.as_mut_ptr();
+        let (first, second) = unsafe { (base.add(i), base.add(j)) };
+        let second = OwnedRefMut::share(&values, second);
+        Some([OwnedRefMut::project(values, first), second])
+    }
+}
+
+impl<T: ?Sized> OwnedRefCell<OwnedRefCell<T>> {
+    /// Borrows the outer cell immutably and the inner cell mutably, returning a reference to the
+    /// inner value that keeps both borrows.
+    ///
+    /// The two layers keep their own borrow state: while the reference is alive, other code can
+    /// still borrow the outer cell immutably but not the inner cell, and nothing can borrow the
+    /// outer cell mutably to swap the inner cell out.
+    /// Panics if the outer cell is already borrowed mutably or the inner cell is already
+    /// borrowed immutably or mutably.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn borrow_nested_mut(&self) -> NestedRefMut<T> {
+        let outer = self.borrow();
+        NestedRefMut {
+            inner: outer.borrow_mut(),
+            _outer: outer,
+        }
+    }
+}
+
+impl<T: ?Sized> OwnedRefCell<Box<T>> {
+    /// Replaces the boxed value with `new`, returning the previous box.
+    /// Panics if the cell is already borrowed immutably or mutably.
+    ///
+    /// Only the box pointers are swapped, so the boxed values themselves are never moved, which
+    /// keeps replacing large payloads cheap. It also works for unsized values such as
+    /// `Box<[T]>` or `Box<dyn Trait>`.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn replace_boxed(&self, new: Box<T>) -> Box<T> {
+        mem::replace(&mut *self.borrow_mut(), new)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K, V, S> OwnedRefCell<HashMap<K, V, S>> {
+    /// Retains only the entries for which `f` returns `true`, under a single mutable borrow.
+    /// Panics if the cell is already borrowed immutably or mutably.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn retain<F: FnMut(&K, &mut V) -> bool>(&self, f: F) {
+        self.borrow_mut().retain(f)
+    }
+
+    /// Takes all entries out of the map, leaving it empty, and returns them.
+    /// Panics if the cell is already borrowed immutably or mutably.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn drain_collect(&self) -> HashMap<K, V, S>
+    where
+        S: Default,
+    {
+        mem::take(&mut *self.borrow_mut())
+    }
+}
+
+impl<T: ?Sized> OwnedRef<T> {
+    /// Upgrades the immutable reference to a mutable one if it is the only active reader.
+    /// Returns the original `OwnedRef` if other readers or a writer are still alive, or if it
+    /// was made by `OwnedRef::map` or the like: those point at the value through a shared
+    /// reference, which must never be written through.
+    ///
+    /// The transition happens without releasing the borrow, so no other writer can acquire the
+    /// cell in between. This is an associated function that needs to be used as
+    /// `OwnedRef::try_upgrade(...)`, so that it does not shadow methods on the inner value.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn try_upgrade(orig: OwnedRef<T>) -> Result<OwnedRefMut<T>, OwnedRef<T>> {
+        let state = &orig.state;
+        if state.reading_count.get() != 1 || state.is_writing.get() || !orig.writable {
+            return Err(orig);
+        }
+        state.reading_count.set(0);
+        state.is_writing.set(true);
+        state.writing_count.set(1);
+        #[cfg(any(test, feature = "test-util"))]
+        state.ops.borrow_mut().push(BorrowOp::Upgrade);
+        state.assert_consistent();
+        #[cfg(feature = "recursion_detect")]
+        if let Some(key) = orig.recursion_key {
+            RECURSIVE_BORROWS.with(|keys| keys.borrow_mut().remove(&key));
+        }
+        #[cfg(feature = "diagnostics")]
+        state.writer_location.set(Some(Location::caller()));
+
+        // The read borrow has been handed over to the new guard, so `orig` must not run its
+        // `Drop` impl; its state handle is moved out instead of being cloned.
+        let orig = ManuallyDrop::new(orig);
+        #[cfg(feature = "callbacks")]
+        orig.state
+            .write_callback
+            .set(unsafe { ptr::read(&orig.on_release) });
+        Ok(OwnedRefMut {
+            value: orig.value as *mut T,
+            state: unsafe { ptr::read(&orig.state) },
+            projected: orig.projected,
+            #[cfg(feature = "diagnostics")]
+            id: orig.id,
+            #[cfg(feature = "recursion_detect")]
+            reentrancy_key: None,
+        })
+    }
+
+    /// Makes a new `OwnedRef` for a component of the borrowed value, keeping the read borrow.
+    ///
+    /// This is an associated function that needs to be used as `OwnedRef::map(...)`, so that it
+    /// does not shadow methods on the inner value.
+    ///
+    /// ```
+    /// use owned_ref_cell::{OwnedRef, OwnedRefCell};
+    ///
+    /// struct Index {
+    ///     values: Vec<i32>,
+    /// }
+    ///
+    /// let cell = OwnedRefCell::new(Index { values: vec![1, 2] });
+    /// let values: OwnedRef<Vec<i32>> = OwnedRef::map(cell.borrow(), |index| &index.values);
+    /// assert_eq!(*values, [1, 2]);
+    /// assert!(cell.try_borrow_mut().is_none());
+    /// ```
+    pub fn map<U: ?Sized, F: FnOnce(&T) -> &U>(orig: OwnedRef<T>, f: F) -> OwnedRef<U> {
+        let value: *const U = f(&orig);
+        OwnedRef::project(orig, value)
+    }
+
+    /// Makes a new `OwnedRef` for an optional component of the borrowed value, keeping the read
+    /// borrow. If `f` returns `None`, the original reference is handed back instead, like
+    /// `Ref::filter_map`.
+    ///
+    /// This is an associated function that needs to be used as `OwnedRef::filter_map(...)`, so
+    /// that it does not shadow methods on the inner value.
+    ///
+    /// ```
+    /// use owned_ref_cell::{OwnedRef, OwnedRefCell};
+    ///
+    /// let cell = OwnedRefCell::new(Ok::<u32, String>(5));
+    /// let err = OwnedRef::filter_map(cell.borrow(), |result| result.as_ref().err());
+    /// let ok = err.err().unwrap();
+    /// assert_eq!(*ok, Ok(5));
+    /// ```
+    pub fn filter_map<U: ?Sized, F: FnOnce(&T) -> Option<&U>>(
+        orig: OwnedRef<T>,
+        f: F,
+    ) -> Result<OwnedRef<U>, OwnedRef<T>> {
+        match f(&orig) {
+            Some(value) => {
+                let value: *const U = value;
+                Ok(OwnedRef::project(orig, value))
+            }
+            None => Err(orig),
+        }
+    }
+
+    /// Returns the version of the cell at the time this reference was borrowed, see
+    /// `OwnedRefCell::version`.
+    ///
+    /// This is an associated function that needs to be used as `OwnedRef::version(...)`, so
+    /// that it does not shadow methods on the inner value.
+    pub fn version(orig: &OwnedRef<T>) -> u64 {
+        orig.version
+    }
+
+    /// Makes another immutable reference to the same value if fewer than `max_readers` read
+    /// borrows of the cell are alive, including `orig` itself. Returns `None` at capacity.
+    ///
+    /// This is an associated function that needs to be used as `OwnedRef::try_clone(...)`, so
+    /// that it does not shadow methods on the inner value.
+    pub fn try_clone(orig: &OwnedRef<T>, max_readers: usize) -> Option<OwnedRef<T>> {
+        if orig.state.reading_count.get() >= max_readers {
+            return None;
+        }
+        Some(OwnedRef::share(orig))
+    }
+
+    /// Makes `n` more immutable references to the same value, in a single allocation, for
+    /// handing one to each of several subscribers. Each of them holds its own read borrow, so
+    /// dropping the whole slice releases exactly `n` read borrows.
+    ///
+    /// This is an associated function that needs to be used as `OwnedRef::broadcast(...)`, so
+    /// that it does not shadow methods on the inner value.
+    pub fn broadcast(orig: &OwnedRef<T>, n: usize) -> Rc<[OwnedRef<T>]> {
+        (0..n).map(|_| OwnedRef::share(orig)).collect()
+    }
+
+    /// Returns the id of this reference, which is unique among the references issued by its
+    /// cell and stays the same when the reference is mapped or upgraded. Useful for telling
+    /// guards apart in logs; only available with the `diagnostics` feature.
+    ///
+    /// This is an associated function that needs to be used as `OwnedRef::id(...)`, so that it
+    /// does not shadow methods on the inner value.
+    #[cfg(feature = "diagnostics")]
+    pub fn id(orig: &OwnedRef<T>) -> u64 {
+        orig.id
+    }
+
+    /// Takes a new read borrow of the value at `value` tracked by `state`, unless it is borrowed
+    /// mutably.
+    fn acquire(value: *const T, state: &Rc<BorrowState>) -> Option<OwnedRef<T>> {
+        if state.is_writing.get() || state.reading_count.get() == usize::MAX {
+            return None;
+        }
+        state.reading_count.set(state.reading_count.get() + 1);
+        #[cfg(any(test, feature = "test-util"))]
+        state.ops.borrow_mut().push(BorrowOp::Borrow);
+        state.assert_consistent();
+        #[cfg(feature = "depth_tracking")]
+        enter_guard();
+        Some(OwnedRef {
+            value,
+            state: Rc::clone(state),
+            #[cfg(feature = "callbacks")]
+            on_release: None,
+            version: state.version.get(),
+            writable: true,
+            projected: false,
+            #[cfg(feature = "diagnostics")]
+            id: state.next_guard_id(),
+            #[cfg(feature = "recursion_detect")]
+            recursion_key: None,
+        })
+    }
+
+    /// Makes an additional reference to the same value as `orig`, with a read borrow of its own.
+    fn share(orig: &OwnedRef<T>) -> OwnedRef<T> {
+        let state = &orig.state;
+        state.reading_count.set(
+            state
+                .reading_count
+                .get()
+                .checked_add(1)
+                .expect("Failed to borrow: too many immutable borrows"),
+        );
+        if orig.projected {
+            state.projected.set(state.projected.get() + 1);
+        }
+        #[cfg(any(test, feature = "test-util"))]
+        state.ops.borrow_mut().push(BorrowOp::Borrow);
+        state.assert_consistent();
+        #[cfg(feature = "depth_tracking")]
+        enter_guard();
+        OwnedRef {
+            value: orig.value,
+            state: Rc::clone(&orig.state),
+            #[cfg(feature = "callbacks")]
+            on_release: None,
+            version: orig.version,
+            writable: orig.writable,
+            projected: orig.projected,
+            #[cfg(feature = "diagnostics")]
+            id: state.next_guard_id(),
+            #[cfg(feature = "recursion_detect")]
+            recursion_key: None,
+        }
+    }
+
+    /// Moves the read borrow held by `orig` into a new reference pointing at `value`, which must
+    /// point into the value borrowed by `orig`.
+    fn project<U: ?Sized>(orig: OwnedRef<T>, value: *const U) -> OwnedRef<U> {
+        if !orig.projected {
+            orig.state.projected.set(orig.state.projected.get() + 1);
+        }
+        let orig = ManuallyDrop::new(orig);
+        OwnedRef {
+            value,
+            state: unsafe { ptr::read(&orig.state) },
+            #[cfg(feature = "callbacks")]
+            on_release: unsafe { ptr::read(&orig.on_release) },
+            version: orig.version,
+            writable: false,
+            projected: true,
+            #[cfg(feature = "diagnostics")]
+            id: orig.id,
+            #[cfg(feature = "recursion_detect")]
+            recursion_key: orig.recursion_key,
+        }
+    }
+}
+
+impl<T: ?Sized> WeakOwnedRef<T> {
+    /// Borrows the cell immutably if it is still alive.
+    /// Returns `None` if the cell has been dropped or is borrowed mutably.
+    pub fn upgrade(&self) -> Option<OwnedRef<T>> {
+        let state = self.state.upgrade()?;
+        if state.cell_dropped.get() {
+            return None;
+        }
+        OwnedRef::acquire(self.value, &state)
+    }
+
+    /// Upgrades each of the handles in `weaks`, like calling `upgrade` on every one of them.
+    ///
+    /// This is an associated function that needs to be used as `WeakOwnedRef::upgrade_all(...)`.
+    pub fn upgrade_all(weaks: &[WeakOwnedRef<T>]) -> Vec<Option<OwnedRef<T>>> {
+        weaks.iter().map(WeakOwnedRef::upgrade).collect()
+    }
+}
+
+impl<T: ?Sized> OwnedRefMut<T> {
+    /// Makes a new `OwnedRefMut` for a component of the borrowed value, keeping the write
+    /// borrow.
+    ///
+    /// This is an associated function that needs to be used as `OwnedRefMut::map(...)`, so that
+    /// it does not shadow methods on the inner value.
+    ///
+    /// ```
+    /// use owned_ref_cell::{OwnedRefCell, OwnedRefMut};
+    ///
+    /// struct Queue {
+    ///     pending: Vec<u32>,
+    /// }
+    ///
+    /// let cell = OwnedRefCell::new(Queue { pending: Vec::new() });
+    /// let mut pending = OwnedRefMut::map(cell.borrow_mut(), |queue| &mut queue.pending);
+    /// pending.push(7);
+    /// assert!(cell.try_borrow().is_none());
+    /// drop(pending);
+    /// assert_eq!(cell.borrow().pending, [7]);
+    /// ```
+    pub fn map<U: ?Sized, F: FnOnce(&mut T) -> &mut U>(
+        mut orig: OwnedRefMut<T>,
+        f: F,
+    ) -> OwnedRefMut<U> {
+        let value: *mut U = f(&mut orig);
+        OwnedRefMut::project(orig, value)
+    }
+
+    /// Makes a new `OwnedRefMut` for an optional component of the borrowed value, keeping the
+    /// write borrow. If `f` returns `None`, the original reference is handed back instead, so the
+    /// write borrow is not released, like `RefMut::filter_map`.
+    ///
+    /// This is an associated function that needs to be used as `OwnedRefMut::try_map(...)`, so
+    /// that it does not shadow methods on the inner value.
+    ///
+    /// ```
+    /// use owned_ref_cell::{OwnedRefCell, OwnedRefMut};
+    ///
+    /// enum Connection {
+    ///     Idle,
+    ///     Open { sent: u32 },
+    /// }
+    ///
+    /// let cell = OwnedRefCell::new(Connection::Idle);
+    /// let idle = OwnedRefMut::try_map(cell.borrow_mut(), |connection| match connection {
+    ///     Connection::Open { sent } => Some(sent),
+    ///     Connection::Idle => None,
+    /// });
+    /// let mut connection = idle.err().unwrap();
+    /// *connection = Connection::Open { sent: 0 };
+    /// ```
+    pub fn try_map<U: ?Sized, F: FnOnce(&mut T) -> Option<&mut U>>(
+        mut orig: OwnedRefMut<T>,
+        f: F,
+    ) -> Result<OwnedRefMut<U>, OwnedRefMut<T>> {
+        match f(&mut orig) {
+            Some(value) => {
+                let value: *mut U = value;
+                Ok(OwnedRefMut::project(orig, value))
+            }
+            None => Err(orig),
+        }
+    }
+
+    /// Returns the id of this reference, which is unique among the references issued by its
+    /// cell. Only available with the `diagnostics` feature.
+    ///
+    /// This is an associated function that needs to be used as `OwnedRefMut::id(...)`, so that
+    /// it does not shadow methods on the inner value.
+    #[cfg(feature = "diagnostics")]
+    pub fn id(orig: &OwnedRefMut<T>) -> u64 {
+        orig.id
+    }
+
+    /// Splits the mutable reference into two references to the disjoint components of the
+    /// value selected by `f`, like `RefMut::map_split`. Both references share the write borrow,
+    /// which is only released once both of them have been dropped.
+    ///
+    /// This is an associated function that needs to be used as `OwnedRefMut::map_split(...)`,
+    /// so that it does not shadow methods on the inner value.
+    ///
+    /// ```
+    /// use owned_ref_cell::{OwnedRefCell, OwnedRefMut};
+    ///
+    /// let cell = OwnedRefCell::new((String::new(), 0));
+    /// let (mut name, mut count) =
+    ///     OwnedRefMut::map_split(cell.borrow_mut(), |(name, count)| (name, count));
+    /// name.push_str("events");
+    /// *count += 1;
+    /// drop(name);
+    /// assert!(cell.try_borrow().is_none());
+    /// drop(count);
+    /// assert_eq!(*cell.borrow(), (String::from("events"), 1));
+    /// ```
+    pub fn map_split<U: ?Sized, V: ?Sized, F>(
+        mut orig: OwnedRefMut<T>,
+        f: F,
+    ) -> (OwnedRefMut<U>, OwnedRefMut<V>)
+    where
+        F: FnOnce(&mut T) -> (&mut U, &mut V),
+    {
+        let (first, second) = f(&mut orig);
+        let (first, second) = (first as *mut U, second as *mut V);
+        let second = OwnedRefMut::share(&orig, second);
+        (OwnedRefMut::project(orig, first), second)
+    }
+
+    /// Splits the mutable reference into two references to the disjoint components of the
+    /// value selected by `f`, which share the write borrow. If `f` fails, its error is returned
+    /// together with `orig`, so the write borrow is kept.
+    ///
+    /// This is an associated function that needs to be used as `OwnedRefMut::try_map_split(...)`,
+    /// so that it does not shadow methods on the inner value.
+    #[allow(clippy::type_complexity)]
+    pub fn try_map_split<U: ?Sized, V: ?Sized, E, F>(
+        mut orig: OwnedRefMut<T>,
+        f: F,
+    ) -> Result<(OwnedRefMut<U>, OwnedRefMut<V>), (E, OwnedRefMut<T>)>
+    where
+        F: FnOnce(&mut T) -> Result<(&mut U, &mut V), E>,
+    {
+        let (first, second) = match f(&mut orig) {
+            Ok((first, second)) => (first as *mut U, second as *mut V),
+            Err(err) => return Err((err, orig)),
+        };
+        let second = OwnedRefMut::share(&orig, second);
+        Ok((OwnedRefMut::project(orig, first), second))
+    }
+
+    /// Turns the mutable reference into an immutable one without releasing the borrow in
+    /// between, so no writer can take the cell before the new reader has it. This is the same as
+    /// `OwnedRef::from(orig)`.
+    ///
+    /// If `orig` shares its write borrow with other references, such as the other half of a
+    /// split, the cell stays closed to readers until those are dropped or downgraded as well.
+    /// This is an associated function that needs to be used as `OwnedRefMut::downgrade(...)`, so
+    /// that it does not shadow methods on the inner value.
+    pub fn downgrade(orig: OwnedRefMut<T>) -> OwnedRef<T> {
+        OwnedRef::from(orig)
+    }
+
+    /// Turns a batch of mutable references into immutable ones, like calling `OwnedRef::from` on
+    /// each of them.
+    ///
+    /// References that share a write borrow, such as the halves returned by `split_at_mut`,
+    /// keep the cell closed to other writers and readers until the last of them has been
+    /// converted, at which point the write flag is cleared once and the cell only has readers.
+    /// This is an associated function that needs to be used as `OwnedRefMut::freeze_all(...)`.
+    pub fn freeze_all(guards: Vec<OwnedRefMut<T>>) -> Vec<OwnedRef<T>> {
+        guards.into_iter().map(OwnedRef::from).collect()
+    }
+
+    /// Makes an additional reference pointing at `value` that shares the write borrow held by
+    /// `orig`, which stays valid. `value` must point into the value borrowed by `orig` and must
+    /// not overlap with anything that `orig` is still used to access.
+    fn share<U: ?Sized>(orig: &OwnedRefMut<T>, value: *mut U) -> OwnedRefMut<U> {
+        let state = &orig.state;
+        state.projected.set(state.projected.get() + 1);
+        state.writing_count.set(state.writing_count.get() + 1);
+        #[cfg(any(test, feature = "test-util"))]
+        state.ops.borrow_mut().push(BorrowOp::BorrowMut);
+        state.assert_consistent();
+        #[cfg(feature = "depth_tracking")]
+        enter_guard();
+        OwnedRefMut {
+            value,
+            state: Rc::clone(&orig.state),
+            projected: true,
+            #[cfg(feature = "diagnostics")]
+            id: state.next_guard_id(),
+            #[cfg(feature = "recursion_detect")]
+            reentrancy_key: orig.reentrancy_key,
+        }
+    }
+
+    /// Moves the write borrow held by `orig` into a new reference pointing at `value`, which
+    /// must point into the value borrowed by `orig`.
+    fn project<U: ?Sized>(orig: OwnedRefMut<T>, value: *mut U) -> OwnedRefMut<U> {
+        if !orig.projected {
+            orig.state.projected.set(orig.state.projected.get() + 1);
+        }
+        let orig = ManuallyDrop::new(orig);
+        OwnedRefMut {
+            value,
+            state: unsafe { ptr::read(&orig.state) },
+            projected: true,
+            #[cfg(feature = "diagnostics")]
+            id: orig.id,
+            #[cfg(feature = "recursion_detect")]
+            reentrancy_key: orig.reentrancy_key,
+        }
+    }
+}
+
+impl<T> Memo<T> {
+    /// Constructs a new `Memo` with no cached value.
+    pub fn new() -> Self {
+        Memo {
+            cell: OwnedRefCell::new(None),
+        }
+    }
+
+    /// Returns a reference to the cached value, computing it with `f` first if there is none.
+    ///
+    /// `f` runs under a mutable borrow of the cache, so no reader can observe the value while it
+    /// is being inserted. Panics if `f` itself tries to access the `Memo`.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn get_or_compute<F: FnOnce() -> T>(&self, f: F) -> OwnedRef<T> {
+        if self.cell.borrow().is_none() {
+            let mut slot = self.cell.borrow_mut();
+            *slot = Some(f());
+        }
+        let cached = self.cell.borrow();
+        let value: *const T = cached.as_ref().unwrap();
+        OwnedRef::project(cached, value)
+    }
+
+    /// Clears the cached value so that the next `get_or_compute` computes it again.
+    /// Panics if a reference returned by `get_or_compute` is still alive.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub fn invalidate(&self) {
+        *self.cell.borrow_mut() = None;
+    }
+}
+
+impl<T> Default for Memo<T> {
+    fn default() -> Self {
+        Memo::new()
+    }
+}
+
+impl<T> TxnGuard<T> {
+    /// Keeps the changes made through the guard and releases the write borrow.
+    ///
+    /// This is an associated function that needs to be used as `TxnGuard::commit(...)`, so that
+    /// it does not shadow methods on the inner value.
+    pub fn commit(mut txn: TxnGuard<T>) {
+        txn.snapshot = None;
+    }
+}
+
+impl<T> ReentrantOwnedRefCell<T> {
+    /// Constructs a new `ReentrantOwnedRefCell` with the specified value.
+    pub fn new(value: T) -> Self {
+        ReentrantOwnedRefCell {
+            cell: OwnedRefCell::new(value),
+        }
+    }
+}
+
+impl<T: ?Sized> ReentrantOwnedRefCell<T> {
+    /// Borrows the cell immutably.
+    /// Panics if the cell is already borrowed mutably.
+    pub fn borrow(&self) -> OwnedRef<T> {
+        self.cell.borrow()
+    }
+
+    /// Borrows the cell mutably, or re-enters the mutable borrow if it is already held.
+    /// Panics if the cell is already borrowed immutably, including by an `OwnedRef` made from
+    /// part of a split write borrow.
+    ///
+    /// # Safety
+    ///
+    /// If the cell is already borrowed mutably, the returned reference aliases every other live
+    /// mutable reference to the value. Until it is dropped, the caller must not use any of those
+    /// other references to read or write the value.
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    pub unsafe fn borrow_mut(&self) -> OwnedRefMut<T> {
+        let state = &self.cell.state;
+        // Readers can coexist with a write borrow once part of it has been turned into an
+        // `OwnedRef`, and must not be aliased either, so re-entry is refused then.
+        if !state.is_writing.get() || state.reading_count.get() != 0 {
+            return self.cell.borrow_mut();
+        }
+        state.writing_count.set(state.writing_count.get() + 1);
+        #[cfg(any(test, feature = "test-util"))]
+        state.ops.borrow_mut().push(BorrowOp::BorrowMut);
+        state.assert_consistent();
+        #[cfg(feature = "depth_tracking")]
+        enter_guard();
+        OwnedRefMut {
+            value: self.cell.value.get().as_ptr(),
+            state: Rc::clone(&self.cell.state),
+            projected: false,
+            #[cfg(feature = "diagnostics")]
+            id: state.next_guard_id(),
+            #[cfg(feature = "recursion_detect")]
+            reentrancy_key: None,
+        }
+    }
+
+    /// Returns the number of live mutable references to the value, which is zero when the cell
+    /// is not borrowed mutably.
+    pub fn depth(&self) -> usize {
+        self.cell.state.writing_count.get()
+    }
+}
+
+impl<T> LocalOwnedRefCell<T> {
+    /// Constructs a new `LocalOwnedRefCell` with the specified value.
+    pub fn new(value: T) -> Self {
+        let inner = Box::new(LocalInner {
+            borrow: Cell::new(0),
+            cell_dropped: Cell::new(false),
+            value: UnsafeCell::new(value),
+        });
+        LocalOwnedRefCell {
+            inner: NonNull::from(Box::leak(inner)),
+            _marker: PhantomData,
+        }
+    }
+
+    fn inner(&self) -> &LocalInner<T> {
+        // The allocation is only freed once the cell has been dropped.
+        unsafe { self.inner.as_ref() }
+    }
+
+    /// Borrows the cell immutably.
+    /// Panics if the cell is already borrowed mutably.
+    pub fn borrow(&self) -> LocalOwnedRef<T> {
+        self.try_borrow()
+            .expect("Failed to borrow: already mutably borrowed")
+    }
+
+    /// Borrows the cell mutably.
+    /// Panics if the cell is already borrowed.
+    pub fn borrow_mut(&self) -> LocalOwnedRefMut<T> {
+        self.try_borrow_mut()
+            .expect("Failed to borrow mutably: already borrowed")
+    }
+
+    /// Tries to borrow the cell immutably, returning `None` if it is already borrowed mutably.
+    pub fn try_borrow(&self) -> Option<LocalOwnedRef<T>> {
+        let borrow = &self.inner().borrow;
+        if borrow.get() < 0 || borrow.get() == isize::MAX {
+            return None;
+        }
+        borrow.set(borrow.get() + 1);
+        Some(LocalOwnedRef {
+            inner: self.inner,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Tries to borrow the cell mutably, returning `None` if it is already borrowed.
+    pub fn try_borrow_mut(&self) -> Option<LocalOwnedRefMut<T>> {
+        let borrow = &self.inner().borrow;
+        if borrow.get() != 0 {
+            return None;
+        }
+        borrow.set(-1);
+        Some(LocalOwnedRefMut {
+            inner: self.inner,
+            _marker: PhantomData,
+        })
+    }
+}
+
+/// Frees the allocation of a `LocalOwnedRefCell` once the cell has been dropped and the borrow
+/// being released was the last one.
+///
+/// # Safety
+///
+/// `inner` must point to a live allocation created by `LocalOwnedRefCell::new`, which must not be
+/// used again if this frees it.
+unsafe fn release_local<T>(inner: NonNull<LocalInner<T>>) {
+    let shared = inner.as_ref();
+    if shared.borrow.get() == 0 && shared.cell_dropped.get() {
+        drop(Box::from_raw(inner.as_ptr()));
+    }
+}
+
+/// Implements `Drop` for `LocalOwnedRefCell` to free the allocation unless references to it
+/// are still alive.
+impl<T> Drop for LocalOwnedRefCell<T> {
+    fn drop(&mut self) {
+        self.inner().cell_dropped.set(true);
+        unsafe { release_local(self.inner) };
+    }
+}
+
+/// Implements `Deref` for `LocalOwnedRef` to allow dereferencing the immutable reference.
+impl<T> Deref for LocalOwnedRef<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.inner.as_ref().value.get() }
+    }
+}
+
+/// Implements `Drop` for `LocalOwnedRef` to release its read borrow.
+impl<T> Drop for LocalOwnedRef<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let borrow = &self.inner.as_ref().borrow;
+            borrow.set(borrow.get() - 1);
+            release_local(self.inner);
+        }
+    }
+}
+
+/// Implements `Deref` for `LocalOwnedRefMut` to allow dereferencing the mutable reference.
+impl<T> Deref for LocalOwnedRefMut<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.inner.as_ref().value.get() }
+    }
+}
+
+/// Implements `DerefMut` for `LocalOwnedRefMut` to allow mutating the referenced value.
+impl<T> DerefMut for LocalOwnedRefMut<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.inner.as_ref().value.get() }
+    }
+}
+
+/// Implements `Drop` for `LocalOwnedRefMut` to release its write borrow.
+impl<T> Drop for LocalOwnedRefMut<T> {
+    fn drop(&mut self) {
+        unsafe {
+            self.inner.as_ref().borrow.set(0);
+            release_local(self.inner);
+        }
+    }
+}
+
+impl<T> OwnedRefMut<[T]> {
+    /// Splits the mutable reference to a slice into two at `mid`, like `<[T]>::split_at_mut`.
+    /// Panics if `mid > len`.
+    ///
+    /// Both references share the original write borrow, which is only released once both of
+    /// them have been dropped. This is an associated function that needs to be used as
+    /// `OwnedRefMut::split_at_mut(...)`, so that it does not shadow the slice method.
+    ///
+    /// This is the way to mutate several elements of an `OwnedRefCell<[T]>` at once:
+    ///
+    /// ```
+    /// use owned_ref_cell::{OwnedRefCell, OwnedRefMut};
+    ///
+    /// let cell = OwnedRefCell::new(vec![1, 2, 3]);
+    /// let values = OwnedRefMut::map(cell.borrow_mut(), |values| values.as_mut_slice());
+    /// let (mut first, mut rest) = OwnedRefMut::split_at_mut(values, 1);
+    /// first[0] += 10;
+    /// rest[1] += 10;
+    /// drop((first, rest));
+    /// assert_eq!(*cell.borrow(), [11, 2, 13]);
+    /// ```
+    pub fn split_at_mut(
+        mut orig: OwnedRefMut<[T]>,
+        mid: usize,
+    ) -> (OwnedRefMut<[T]>, OwnedRefMut<[T]>) {
+        let (left, right) = (*orig).split_at_mut(mid);
+        let (left, right) = (left as *mut [T], right as *mut [T]);
+        let right = OwnedRefMut::share(&orig, right);
+        (OwnedRefMut::project(orig, left), right)
+    }
+}
+
+/// Mutably borrows several cells at once, all or nothing.
+///
+/// Expands to an `Option` of a tuple with one `OwnedRefMut` per cell, in the order given. The
+/// cells are borrowed one after the other with `try_borrow_mut`; if any of them is already
+/// borrowed, the borrows acquired so far are released again and the macro evaluates to `None`.
+///
+/// The cells must be distinct: passing the same cell twice always evaluates to `None`, since
+/// its second mutable borrow conflicts with the first.
+///
+/// ```
+/// use owned_ref_cell::{borrow_mut_all, OwnedRefCell};
+///
+/// let a = OwnedRefCell::new(1);
+/// let b = OwnedRefCell::new(2);
+/// let c = OwnedRefCell::new(3);
+///
+/// let (mut a_ref, mut b_ref, c_ref) = borrow_mut_all!(a, b, c).unwrap();
+/// *a_ref += *c_ref;
+/// *b_ref += *c_ref;
+/// drop((a_ref, b_ref, c_ref));
+///
+/// let _b_ref = b.borrow();
+/// assert!(borrow_mut_all!(a, b, c).is_none());
+/// assert!(a.try_borrow_mut().is_some());
+/// ```
+#[macro_export]
+macro_rules! borrow_mut_all {
+    (@acquire [$($guard:ident)*] $cell:expr $(, $rest:expr)*) => {
+        match $cell.try_borrow_mut() {
+            Some(guard) => $crate::borrow_mut_all!(@acquire [$($guard)* guard] $($rest),*),
+            None => None,
+        }
+    };
+    (@acquire [$($guard:ident)*]) => {
+        Some(($($guard,)*))
+    };
+    ($($cell:expr),+ $(,)?) => {
+        $crate::borrow_mut_all!(@acquire [] $($cell),+)
+    };
+}
+
+/// Borrows a field of the value in a cell, keeping the cell borrowed for as long as the returned
+/// reference is alive.
+///
+/// `project!(cell => path.to.field)` borrows the cell immutably and evaluates to an
+/// `OwnedRef` of the field, while `project!(mut cell => path.to.field)` borrows it mutably and
+/// evaluates to an `OwnedRefMut`. This is shorthand for `OwnedRef::map` and `OwnedRefMut::map`
+/// and panics on conflicting borrows like `borrow` and `borrow_mut`.
+///
+/// ```
+/// use owned_ref_cell::{project, OwnedRefCell};
+/// use std::time::Duration;
+///
+/// struct Config {
+///     timeout: Duration,
+/// }
+///
+/// struct App {
+///     config: Config,
+/// }
+///
+/// let app = OwnedRefCell::new(App {
+///     config: Config {
+///         timeout: Duration::from_secs(1),
+///     },
+/// });
+///
+/// let mut timeout = project!(mut app => config.timeout);
+/// *timeout *= 2;
+/// assert!(app.try_borrow().is_none());
+/// drop(timeout);
+///
+/// assert_eq!(*project!(app => config.timeout), Duration::from_secs(2));
+/// ```
+#[macro_export]
+macro_rules! project {
+    (mut $cell:expr => $($field:tt).+) => {
+        $crate::OwnedRefMut::map($cell.borrow_mut(), |value| &mut value.$($field).+)
+    };
+    ($cell:expr => $($field:tt).+) => {
+        $crate::OwnedRef::map($cell.borrow(), |value| &value.$($field).+)
+    };
+}
+
+/// Implements `Deref` for `OwnedRef` to allow dereferencing the owned reference.
+impl<T: ?Sized> Deref for OwnedRef<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { self.value.as_ref().unwrap() }
+    }
+}
+
+/// Implements `Deref` for `OwnedRefMut` to allow dereferencing the owned mutable reference.
+impl<T: ?Sized> Deref for OwnedRefMut<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { self.value.as_ref().unwrap() }
+    }
+}
+
+/// Implements `DerefMut` for `OwnedRefMut` to allow dereferencing the owned mutable reference.
+impl<T: ?Sized> DerefMut for OwnedRefMut<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { self.value.as_mut().unwrap() }
+    }
+}
+
+/// Implements `Future` for `BorrowMutFuture`, registering the task's waker with the cell while
+/// it is borrowed.
+#[cfg(feature = "async")]
+impl<T: ?Sized> Future for BorrowMutFuture<'_, T> {
+    type Output = OwnedRefMut<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.cell.try_borrow_mut() {
+            Some(guard) => Poll::Ready(guard),
+            None => {
+                self.cell.register_waker(cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Implements `Deref` for `ComputedRef` to compute the derived value on first use.
+impl<T: ?Sized, U> Deref for ComputedRef<T, U> {
+    type Target = U;
+
+    fn deref(&self) -> &Self::Target {
+        self.cached.get_or_init(|| (self.compute)(&self.guard))
+    }
+}
+
+/// Implements `Deref` for `NestedRefMut` to give access to the inner value.
+impl<T: ?Sized> Deref for NestedRefMut<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+/// Implements `DerefMut` for `NestedRefMut` to allow mutating the inner value.
+impl<T: ?Sized> DerefMut for NestedRefMut<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+/// Implements `Deref` for `TxnGuard` to give access to the value being edited.
+impl<T> Deref for TxnGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+/// Implements `DerefMut` for `TxnGuard` to allow editing the value.
+impl<T> DerefMut for TxnGuard<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+/// Implements `Deref` for `Snapshot` to give access to the copied value.
+impl<T> Deref for Snapshot<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+/// Implements `Extend` for `OwnedRefMut` to allow extending a borrowed collection in place.
+///
+/// ```
+/// use owned_ref_cell::OwnedRefCell;
+/// use std::collections::HashMap;
+///
+/// let shared_map = OwnedRefCell::new(HashMap::new());
+/// shared_map
+///     .borrow_mut()
+///     .extend([("green", 92388), ("blue", 11837), ("red", 11826)]);
+///
+/// let total: i32 = shared_map.borrow().values().sum();
+/// assert_eq!(total, 116051);
+/// ```
+impl<A, C: Extend<A>> Extend<A> for OwnedRefMut<C> {
+    fn extend<I: IntoIterator<Item = A>>(&mut self, iter: I) {
+        (**self).extend(iter)
+    }
+}
+
+/// Implements `Write` for `OwnedRefMut` by forwarding to the referenced writer, so that a borrow
+/// of a shared buffer can be passed wherever an `impl Write` is expected.
+///
+/// ```
+/// use owned_ref_cell::OwnedRefCell;
+/// use std::io::Write;
+///
+/// fn greet(mut out: impl Write) {
+///     write!(out, "hello").unwrap();
+/// }
+///
+/// let buffer = OwnedRefCell::new(Vec::new());
+/// greet(buffer.borrow_mut());
+/// assert_eq!(*buffer.borrow(), b"hello");
+/// ```
+#[cfg(feature = "std")]
+impl<T: ?Sized + io::Write> io::Write for OwnedRefMut<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (**self).write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        (**self).write_all(buf)
+    }
+
+    fn write_fmt(&mut self, args: fmt::Arguments<'_>) -> io::Result<()> {
+        (**self).write_fmt(args)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+}
+
+/// Implements `Read` for `OwnedRefMut` by forwarding to the referenced reader.
+///
+/// `OwnedRef` does not implement `Read`: reading advances the reader, which needs the mutable
+/// access only an `OwnedRefMut` provides.
+#[cfg(feature = "std")]
+impl<T: ?Sized + io::Read> io::Read for OwnedRefMut<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (**self).read(buf)
+    }
+
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        (**self).read_to_end(buf)
+    }
+
+    fn read_to_string(&mut self, buf: &mut String) -> io::Result<usize> {
+        (**self).read_to_string(buf)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        (**self).read_exact(buf)
+    }
+}
+
+/// Implements `PartialEq` for `OwnedRef` by comparing the referenced values.
+impl<T: ?Sized + PartialEq> PartialEq for OwnedRef<T> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+/// Implements `Eq` for `OwnedRef` when the referenced value is `Eq`.
+impl<T: ?Sized + Eq> Eq for OwnedRef<T> {}
+
+/// Implements `Hash` for `OwnedRef` by hashing the referenced value.
+///
+/// `Hash`, `PartialEq` and `Borrow` all forward to the referenced value, so an `OwnedRef<K>`
+/// hashes and compares exactly like the `K` it points to. This keeps the `Hash`/`Eq` contract
+/// intact and lets a `HashMap<OwnedRef<K>, V>` be queried with a plain `&K`.
+///
+/// Clippy's `mutable_key_type` lint flags such maps because the guard holds the cell's borrow
+/// state. That state takes no part in hashing or equality, so the lint can be safely allowed.
+impl<T: ?Sized + Hash> Hash for OwnedRef<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+/// Implements `Borrow` for `OwnedRef` so it can be looked up by the referenced value.
+impl<T: ?Sized> core::borrow::Borrow<T> for OwnedRef<T> {
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+/// Implements `AsRef` for `OwnedRef` by forwarding to the referenced value, like `&T` does, so an
+/// `OwnedRef<String>` can be passed where an `impl AsRef<str>` is expected.
+impl<T: ?Sized + AsRef<U>, U: ?Sized> AsRef<U> for OwnedRef<T> {
+    fn as_ref(&self) -> &U {
+        (**self).as_ref()
+    }
+}
+
+/// Implements `AsRef` for `OwnedRefMut` by forwarding to the referenced value, like `&mut T`
+/// does.
+impl<T: ?Sized + AsRef<U>, U: ?Sized> AsRef<U> for OwnedRefMut<T> {
+    fn as_ref(&self) -> &U {
+        (**self).as_ref()
+    }
+}
+
+/// Implements `AsMut` for `OwnedRefMut` by forwarding to the referenced value, like `&mut T`
+/// does.
+impl<T: ?Sized + AsMut<U>, U: ?Sized> AsMut<U> for OwnedRefMut<T> {
+    fn as_mut(&mut self) -> &mut U {
+        (**self).as_mut()
+    }
+}
+
+/// Implements `From<OwnedRefMut>` for `OwnedRef` to turn a mutable reference into an immutable one.
+///
+/// The write borrow is exchanged for a read borrow in a single step, so no other writer can
+/// acquire the cell in between.
+impl<T: ?Sized> From<OwnedRefMut<T>> for OwnedRef<T> {
+    fn from(orig: OwnedRefMut<T>) -> Self {
+        let state = &orig.state;
+        state.release_write();
+        state.reading_count.set(
+            state
+                .reading_count
+                .get()
+                .checked_add(1)
+                .expect("Failed to borrow: too many immutable borrows"),
+        );
+        #[cfg(any(test, feature = "test-util"))]
+        state.ops.borrow_mut().push(BorrowOp::Borrow);
+        state.assert_consistent();
+        #[cfg(feature = "recursion_detect")]
+        if let Some(key) = orig.reentrancy_key.filter(|_| !state.is_writing.get()) {
+            GUARDED_WRITES.with(|keys| keys.borrow_mut().remove(&key));
+        }
+        let version = state.version.get();
+
+        // The borrow has been converted in place, so `orig` must not release it again.
+        let orig = ManuallyDrop::new(orig);
+        #[cfg(feature = "callbacks")]
+        if let Some(on_release) = orig.state.take_write_callback_if_released() {
+            on_release();
+        }
+        OwnedRef {
+            value: orig.value,
+            state: unsafe { ptr::read(&orig.state) },
+            #[cfg(feature = "callbacks")]
+            on_release: None,
+            version,
+            writable: true,
+            projected: orig.projected,
+            #[cfg(feature = "diagnostics")]
+            id: orig.id,
+            #[cfg(feature = "recursion_detect")]
+            recursion_key: None,
+        }
+    }
+}
+
+/// Implements `Clone` for `OwnedRef` to make another reference to the same value, with a read
+/// borrow of its own. Since method calls resolve to this impl first, `r.clone()` clones the
+/// reference; use `(*r).clone()` to clone the value instead.
+impl<T: ?Sized> Clone for OwnedRef<T> {
+    fn clone(&self) -> Self {
+        OwnedRef::share(self)
+    }
+}
+
+/// Implements `Clone` for `WeakOwnedRef` to make another weak handle to the same cell.
+impl<T: ?Sized> Clone for WeakOwnedRef<T> {
+    fn clone(&self) -> Self {
+        WeakOwnedRef {
+            value: self.value,
+            state: Weak::clone(&self.state),
+        }
+    }
+}
+
+/// Implements `Default` for `OwnedRefCell` to create an unborrowed cell holding `T::default()`.
+impl<T: Default> Default for OwnedRefCell<T> {
+    fn default() -> Self {
+        OwnedRefCell::new(T::default())
+    }
+}
+
+/// Implements `PartialEq` for `OwnedRefCell` like `RefCell` does, comparing the values under
+/// immutable borrows.
+/// Panics if either cell is currently borrowed mutably.
+impl<T: ?Sized + PartialEq> PartialEq for OwnedRefCell<T> {
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    fn eq(&self, other: &OwnedRefCell<T>) -> bool {
+        *self.borrow() == *other.borrow()
+    }
+}
+
+/// Implements `Eq` for `OwnedRefCell` when the value is `Eq`.
+impl<T: ?Sized + Eq> Eq for OwnedRefCell<T> {}
+
+/// Implements `PartialOrd` for `OwnedRefCell` like `RefCell` does, comparing the values under
+/// immutable borrows.
+/// Panics if either cell is currently borrowed mutably.
+impl<T: ?Sized + PartialOrd> PartialOrd for OwnedRefCell<T> {
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    fn partial_cmp(&self, other: &OwnedRefCell<T>) -> Option<Ordering> {
+        self.borrow().partial_cmp(&*other.borrow())
+    }
+
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    fn lt(&self, other: &OwnedRefCell<T>) -> bool {
+        *self.borrow() < *other.borrow()
+    }
+
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    fn le(&self, other: &OwnedRefCell<T>) -> bool {
+        *self.borrow() <= *other.borrow()
+    }
+
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    fn gt(&self, other: &OwnedRefCell<T>) -> bool {
+        *self.borrow() > *other.borrow()
+    }
+
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    fn ge(&self, other: &OwnedRefCell<T>) -> bool {
+        *self.borrow() >= *other.borrow()
+    }
+}
+
+/// Implements `Ord` for `OwnedRefCell` like `RefCell` does, comparing the values under immutable
+/// borrows.
+/// Panics if either cell is currently borrowed mutably.
+impl<T: ?Sized + Ord> Ord for OwnedRefCell<T> {
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    fn cmp(&self, other: &OwnedRefCell<T>) -> Ordering {
+        self.borrow().cmp(&*other.borrow())
+    }
+}
+
+/// Implements `Hash` for `OwnedRefCell` by hashing the value under an immutable borrow, so that
+/// it agrees with `PartialEq`. The value must not be changed while the cell is a key of a map or
+/// set.
+/// Panics if the cell is currently borrowed mutably.
+impl<T: ?Sized + Hash> Hash for OwnedRefCell<T> {
+    #[cfg_attr(feature = "diagnostics", track_caller)]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.borrow().hash(state)
+    }
+}
+
+/// Implements `Debug` for `OwnedRefCell` like `RefCell` does, printing the value if the cell can
+/// be borrowed immutably and `<borrowed>` while it is borrowed mutably.
+impl<T: ?Sized + fmt::Debug> fmt::Debug for OwnedRefCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        struct BorrowedPlaceholder;
+
+        impl fmt::Debug for BorrowedPlaceholder {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("<borrowed>")
+            }
+        }
+
+        let mut d = f.debug_struct("OwnedRefCell");
+        match self.try_borrow() {
+            Some(value) => d.field("value", &&*value),
+            None => d.field("value", &BorrowedPlaceholder),
+        };
+        d.finish()
+    }
+}
+
+/// Implements `Debug` for `OwnedRef` by forwarding to the referenced value.
+impl<T: ?Sized + fmt::Debug> fmt::Debug for OwnedRef<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+/// Implements `Debug` for `OwnedRefMut` by forwarding to the referenced value.
+impl<T: ?Sized + fmt::Debug> fmt::Debug for OwnedRefMut<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+/// Implements `Serialize` for `OwnedRefCell` like `RefCell` does, serializing the value through an
+/// immutable borrow and failing while the cell is borrowed mutably.
+///
+/// Only available with the `serde` feature.
+#[cfg(feature = "serde")]
+impl<T: ?Sized + serde::Serialize> serde::Serialize for OwnedRefCell<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.try_borrow() {
+            Some(value) => (*value).serialize(serializer),
+            None => Err(serde::ser::Error::custom("already mutably borrowed")),
+        }
+    }
+}
+
+/// Implements `Deserialize` for `OwnedRefCell` to create an unborrowed cell holding the
+/// deserialized value.
+///
+/// Only available with the `serde` feature.
+#[cfg(feature = "serde")]
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for OwnedRefCell<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(OwnedRefCell::new)
+    }
+}
+
+/// Implements `fmt::Pointer` for `OwnedRefCell` to format the address of the value, for
+/// correlating cells and references in logs. The borrow state is not touched.
+impl<T: ?Sized> fmt::Pointer for OwnedRefCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Pointer::fmt(&self.value.get().as_ptr(), f)
+    }
+}
+
+/// Implements `fmt::Pointer` for `OwnedRef` to format the address of the referenced value.
+impl<T: ?Sized> fmt::Pointer for OwnedRef<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Pointer::fmt(&self.value, f)
+    }
+}
+
+/// Implements `fmt::Pointer` for `OwnedRefMut` to format the address of the referenced value.
+impl<T: ?Sized> fmt::Pointer for OwnedRefMut<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Pointer::fmt(&self.value, f)
+    }
+}
+
+impl fmt::Display for BorrowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("already mutably borrowed")
+    }
+}
+
+impl Error for BorrowError {}
+
+impl fmt::Display for BorrowMutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("already borrowed")
+    }
+}
+
+impl Error for BorrowMutError {}
+
+#[cfg(any(test, feature = "test-util"))]
+impl fmt::Display for ModelViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "borrow model violated at step {}: {}",
+            self.step, self.message
+        )
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl Error for ModelViolation {}
+
+#[cfg(feature = "recursion_detect")]
+impl fmt::Display for RecursionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("already borrowed recursively")
+    }
+}
+
+#[cfg(feature = "recursion_detect")]
+impl Error for RecursionError {}
+
+#[cfg(feature = "depth_tracking")]
+impl fmt::Display for DepthLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("borrow depth limit reached")
+    }
+}
+
+#[cfg(feature = "depth_tracking")]
+impl Error for DepthLimitError {}
+
+#[cfg(feature = "recursion_detect")]
+impl fmt::Display for ReentrantBorrowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("already borrowed mutably further up the call stack")
+    }
+}
+
+#[cfg(feature = "recursion_detect")]
+impl Error for ReentrantBorrowError {}
+
+/// Implements `Drop` for `OwnedRefCell` so that its `WeakOwnedRef`s can no longer be upgraded.
+impl<T: ?Sized> Drop for OwnedRefCell<T> {
+    fn drop(&mut self) {
+        self.state.cell_dropped.set(true);
+        if self.fork_clone.get().is_some() {
+            // The value is still shared with the cell this one was forked from, and is released
+            // along with the borrow state.
+        } else if self.state.is_borrowed() {
+            self.state
+                .orphan
+                .set(Some(OrphanedValue::new(self.value.get())));
+        } else {
+            drop(unsafe { Box::from_raw(self.value.get().as_ptr()) });
+        }
+    }
+}
+
+impl OrphanedValue {
+    fn new<T: ?Sized>(value: NonNull<T>) -> Self {
+        unsafe fn drop_value<T: ?Sized>(value: *mut ()) {
+            let value = Box::from_raw(value as *mut NonNull<T>);
+            drop(Box::from_raw(value.as_ptr()));
+        }
+
+        OrphanedValue {
+            value: Box::into_raw(Box::new(value)) as *mut (),
+            drop: drop_value::<T>,
+        }
+    }
+}
+
+/// Implements `Drop` for `ForkSource` to release the read borrow it holds.
+impl Drop for ForkSource {
+    fn drop(&mut self) {
+        unsafe { (self.drop)(self.guard) };
+    }
+}
+
+/// Implements `Drop` for `BorrowState` to drop the value of a cell that was dropped while still
+/// borrowed, once its last reference is gone.
+impl Drop for BorrowState {
+    fn drop(&mut self) {
+        if let Some(orphan) = self.orphan.take() {
+            // The last reference may have been to a component of the value and outlived the
+            // lifetimes in the type of the value, in which case it is still counted.
+            if self.projected.get() == 0 {
+                unsafe { (orphan.drop)(orphan.value) };
+            }
+        }
+    }
+}
+
+/// Implements `Drop` for `OwnedRef` and `OwnedRefMut` to update the borrowing state when the
+/// references are dropped.
+impl<T: ?Sized> Drop for OwnedRef<T> {
+    fn drop(&mut self) {
+        let state = &self.state;
+        state.reading_count.set(state.reading_count.get() - 1);
+        #[cfg(any(test, feature = "test-util"))]
+        state.ops.borrow_mut().push(BorrowOp::Release);
+        state.assert_consistent();
+        if self.projected {
+            state.release_projection();
+        }
+        state.release_fork_source_if_detached();
+        #[cfg(feature = "async")]
+        let wakers = state.take_wakers_if_free();
+        #[cfg(feature = "depth_tracking")]
+        exit_guard();
+        #[cfg(feature = "recursion_detect")]
+        if let Some(key) = self.recursion_key {
+            RECURSIVE_BORROWS.with(|keys| keys.borrow_mut().remove(&key));
+        }
+        #[cfg(feature = "async")]
+        wakers.into_iter().for_each(Waker::wake);
+        #[cfg(feature = "callbacks")]
+        if let Some(on_release) = self.on_release.take() {
+            on_release();
+        }
+    }
+}
+
+/// Implements `Drop` for `TxnGuard` to restore the original value unless the edit was committed.
+impl<T> Drop for TxnGuard<T> {
+    fn drop(&mut self) {
+        if let Some(snapshot) = self.snapshot.take() {
+            *self.guard = snapshot;
+        }
+    }
+}
+
+/// Implements `Drop` for `OwnedRefMut` to update the borrowing state when the reference is dropped.
+impl<T: ?Sized> Drop for OwnedRefMut<T> {
+    fn drop(&mut self) {
+        self.state.release_write();
+        if self.projected {
+            self.state.release_projection();
+        }
+        #[cfg(feature = "async")]
+        let wakers = self.state.take_wakers_if_free();
+        #[cfg(feature = "depth_tracking")]
+        exit_guard();
+        // Every reference sharing a guarded write borrow carries its key, which is only removed
+        // once the last of them has released it.
+        #[cfg(feature = "recursion_detect")]
+        if let Some(key) = self.reentrancy_key.filter(|_| !self.state.is_writing.get()) {
+            GUARDED_WRITES.with(|keys| keys.borrow_mut().remove(&key));
+        }
+        #[cfg(feature = "async")]
+        wakers.into_iter().for_each(Waker::wake);
+        #[cfg(feature = "callbacks")]
+        if let Some(on_release) = self.state.take_write_callback_if_released() {
+            on_release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeSet, HashMap, HashSet},
+        panic::{self, AssertUnwindSafe},
+    };
+
+    use super::*;
+
+    #[test]
+    fn hashmap_borrow_mut_modify_descope_borrow() {
+        let shared_map = OwnedRefCell::new(HashMap::new());
+
+        {
+            let mut map = shared_map.borrow_mut();
+            map.insert("a", 1);
+            map.insert("b", 2);
+            map.insert("c", 3);
+            map.insert("d", 4);
+        }
+
+        let total: i32 = shared_map.borrow().values().sum();
+        assert_eq!(total, 10);
+
+        let map_ref = {
+            let mut map = shared_map.borrow_mut();
+            map.insert("e", 5);
+            map
+        };
+        let total: i32 = map_ref.values().sum();
+        assert_eq!(total, 15);
+    }
+
+    #[test]
+    fn extend_borrow_across_scopes() {
+        let cell = OwnedRefCell::new(10);
+
+        // Function that extends the mutable borrow across its original scope
+        fn extend_borrow<T>(mut borrow: OwnedRefMut<T>, modifier: T) -> OwnedRefMut<T>
+        where
+            T: std::ops::AddAssign,
+        {
+            *borrow += modifier;
+            borrow
+        }
+
+        let borrow_mut = cell.borrow_mut();
+        let extended_borrow = extend_borrow(borrow_mut, 5);
+
+        assert_eq!(*extended_borrow, 15);
+    }
+
+    #[test]
+    fn borrow_mut_modify_and_borrow_after_drop() {
+        let cell = OwnedRefCell::new(10);
+        {
+            let mut b = cell.borrow_mut();
+            *b = 20;
+        }
+        let b = cell.borrow();
+        assert_eq!(*b, 20);
+    }
+
+    #[test]
+    fn cannot_borrow_mut_while_immutably_borrowed() {
+        let cell = OwnedRefCell::new(10);
+        let _b = cell.borrow();
+        assert!(cell.try_borrow_mut().is_none());
+    }
+
+    #[test]
+    fn cannot_borrow_while_mutably_borrowed() {
+        let cell = OwnedRefCell::new(10);
+        let _b = cell.borrow_mut();
+        assert!(cell.try_borrow().is_none());
+    }
+
+    #[test]
+    fn cannot_borrow_mut_while_mutably_borrowed() {
+        let cell = OwnedRefCell::new(10);
+        let _b = cell.borrow_mut();
+        assert!(cell.try_borrow_mut().is_none());
+    }
+
+    #[test]
+    fn multiple_immutable_borrows() {
+        let cell = OwnedRefCell::new(10);
+        let b1 = cell.try_borrow().unwrap();
+        let b2 = cell.try_borrow().unwrap();
+        assert_eq!(*b1, 10);
+        assert_eq!(*b2, 10);
+    }
+
+    #[test]
+    fn multiple_immutable_borrows_after_borrow_mut() {
+        let cell = OwnedRefCell::new(10);
+        {
+            let _b1 = cell.borrow_mut();
+        }
+        let b2 = cell.borrow();
+        let b3 = cell.borrow();
+        assert_eq!(*b2, 10);
+        assert_eq!(*b3, 10);
+    }
+
+    #[test]
+    fn borrow_mut_again_after_drop() {
+        let cell = OwnedRefCell::new(10);
+        {
+            let mut b = cell.borrow_mut();
+            *b = 20;
+        }
+        {
+            let mut b = cell.borrow_mut();
+            *b = 30;
+        }
+        let b = cell.borrow();
+        assert_eq!(*b, 30);
+    }
+
+    #[test]
     fn panic_on_borrow_when_already_borrowed_mutably() {
         let cell = OwnedRefCell::new(50);
         let _b1 = cell.borrow_mut();
@@ -323,13 +3583,2400 @@ mod tests {
     }
 
     #[test]
-    fn panic_on_borrow_mut_when_already_borrowed() {
-        let cell = OwnedRefCell::new(50);
-        let _b1 = cell.borrow();
-        let cell_ref = AssertUnwindSafe(&cell);
-        let result = panic::catch_unwind(move || {
-            cell_ref.borrow_mut();
+    fn panic_on_borrow_mut_when_already_borrowed() {
+        let cell = OwnedRefCell::new(50);
+        let _b1 = cell.borrow();
+        let cell_ref = AssertUnwindSafe(&cell);
+        let result = panic::catch_unwind(move || {
+            cell_ref.borrow_mut();
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn try_upgrade_sole_reader() {
+        let cell = OwnedRefCell::new(10);
+        let b = cell.borrow();
+        let mut b = OwnedRef::try_upgrade(b).ok().unwrap();
+        *b = 20;
+        assert!(cell.try_borrow().is_none());
+        assert!(cell.try_borrow_mut().is_none());
+        drop(b);
+        assert_eq!(*cell.borrow(), 20);
+        assert!(cell.try_borrow_mut().is_some());
+    }
+
+    #[test]
+    fn try_upgrade_fails_with_multiple_readers() {
+        let cell = OwnedRefCell::new(10);
+        let b1 = cell.borrow();
+        let b2 = cell.borrow();
+        let b1 = OwnedRef::try_upgrade(b1).err().unwrap();
+        assert_eq!(*b1, 10);
+        assert_eq!(cell.state.reading_count.get(), 2);
+        drop(b2);
+        assert!(OwnedRef::try_upgrade(b1).is_ok());
+    }
+
+    #[test]
+    fn inspect_releases_borrow() {
+        let cell = OwnedRefCell::new(10);
+        let _b = cell.borrow();
+        let mut seen = 0;
+        cell.inspect(|v| seen = *v);
+        assert_eq!(seen, 10);
+        assert_eq!(cell.state.reading_count.get(), 1);
+    }
+
+    #[test]
+    fn panic_on_inspect_when_already_borrowed_mutably() {
+        let cell = OwnedRefCell::new(10);
+        let _b = cell.borrow_mut();
+        let cell_ref = AssertUnwindSafe(&cell);
+        let result = panic::catch_unwind(move || {
+            cell_ref.inspect(|_| {});
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn compare_and_set_matching_value() {
+        let cell = OwnedRefCell::new(10);
+        assert_eq!(cell.compare_and_set(&10, 20), Ok(10));
+        assert_eq!(*cell.borrow(), 20);
+    }
+
+    #[test]
+    fn compare_and_set_mismatched_value() {
+        let cell = OwnedRefCell::new(10);
+        assert_eq!(cell.compare_and_set(&5, 20), Err(20));
+        assert_eq!(*cell.borrow(), 10);
+    }
+
+    #[test]
+    fn panic_on_compare_and_set_when_already_borrowed() {
+        let cell = OwnedRefCell::new(10);
+        let _b = cell.borrow();
+        let cell_ref = AssertUnwindSafe(&cell);
+        let result = panic::catch_unwind(move || {
+            let _ = cell_ref.compare_and_set(&10, 20);
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn extend_through_borrow_mut() {
+        let cell = OwnedRefCell::new(vec![1, 2]);
+        cell.borrow_mut().extend(vec![3, 4]);
+        assert_eq!(*cell.borrow(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn swap_values() {
+        let a = OwnedRefCell::new(1);
+        let b = OwnedRefCell::new(2);
+        a.swap(&b);
+        assert_eq!(*a.borrow(), 2);
+        assert_eq!(*b.borrow(), 1);
+    }
+
+    #[test]
+    fn try_swap_fails_when_other_is_borrowed() {
+        let a = OwnedRefCell::new(1);
+        let b = OwnedRefCell::new(2);
+        let _b_ref = b.borrow();
+        let err = a.try_swap(&b).unwrap_err();
+        assert_eq!(err.to_string(), "already borrowed");
+        assert_eq!(*a.borrow(), 1);
+        assert!(a.try_borrow_mut().is_some());
+    }
+
+    #[test]
+    fn try_swap_with_self_is_noop() {
+        let a = OwnedRefCell::new(1);
+        assert!(a.try_swap(&a).is_ok());
+        assert_eq!(*a.borrow(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "diagnostics")]
+    fn current_writer_location_points_to_borrow_site() {
+        let cell = OwnedRefCell::new(10);
+        assert!(cell.current_writer_location().is_none());
+        let line = line!() + 1;
+        let b = cell.borrow_mut();
+        let location = cell.current_writer_location().unwrap();
+        assert_eq!(location.file(), file!());
+        assert_eq!(location.line(), line);
+        drop(b);
+        assert!(cell.current_writer_location().is_none());
+    }
+
+    #[test]
+    #[cfg(not(feature = "diagnostics"))]
+    fn current_writer_location_is_none_without_diagnostics() {
+        let cell = OwnedRefCell::new(10);
+        let _b = cell.borrow_mut();
+        assert!(cell.current_writer_location().is_none());
+    }
+
+    #[test]
+    fn fork_mutation_leaves_original_untouched() {
+        let cell = OwnedRefCell::new(vec![1, 2, 3]);
+        let fork = cell.fork();
+        fork.borrow_mut().push(4);
+        assert_eq!(*cell.borrow(), vec![1, 2, 3]);
+        assert_eq!(*fork.borrow(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn fork_has_independent_borrow_state() {
+        let cell = OwnedRefCell::new(10);
+        let fork = cell.fork();
+        let reader = fork.borrow();
+        assert!(fork.try_borrow_mut().is_none());
+        assert!(cell.try_borrow().is_some());
+        drop(reader);
+
+        *fork.borrow_mut() += 1;
+        let _b = cell.borrow_mut();
+        assert!(fork.try_borrow_mut().is_some());
+    }
+
+    #[test]
+    fn fork_copies_on_first_write() {
+        struct Counted(Rc<Cell<usize>>);
+
+        impl Clone for Counted {
+            fn clone(&self) -> Self {
+                self.0.set(self.0.get() + 1);
+                Counted(Rc::clone(&self.0))
+            }
+        }
+
+        let clones = Rc::new(Cell::new(0));
+        let cell = OwnedRefCell::new(Counted(Rc::clone(&clones)));
+        drop(cell.fork());
+        assert!(cell.try_borrow_mut().is_some());
+
+        let fork = cell.fork();
+        drop(fork.borrow());
+        assert_eq!(clones.get(), 0);
+        assert!(cell.try_borrow_mut().is_none());
+
+        drop(fork.borrow_mut());
+        drop(fork.borrow_mut());
+        assert_eq!(clones.get(), 1);
+        assert!(cell.try_borrow_mut().is_some());
+    }
+
+    #[test]
+    fn fork_keeps_shared_value_for_earlier_readers() {
+        let cell = OwnedRefCell::new(String::from("shared"));
+        let fork = cell.fork();
+        let early = fork.borrow();
+        let weak = fork.downgrade();
+        drop(cell);
+        assert_eq!(*early, "shared");
+        drop(early);
+
+        let mut copy = fork.borrow_mut();
+        copy.push('!');
+        drop(copy);
+        assert_eq!(*weak.upgrade().unwrap(), "shared!");
+    }
+
+    #[test]
+    #[allow(clippy::mutable_key_type)]
+    fn owned_ref_as_hashmap_key() {
+        let a = OwnedRefCell::new(String::from("a"));
+        let b = OwnedRefCell::new(String::from("b"));
+        let mut map = HashMap::new();
+        map.insert(a.borrow(), 1);
+        map.insert(b.borrow(), 2);
+        assert_eq!(map.get(&String::from("a")), Some(&1));
+        assert_eq!(map.get(&String::from("b")), Some(&2));
+        assert!(map.contains_key(&a.borrow()));
+    }
+
+    #[test]
+    fn owned_ref_hash_matches_inner_value() {
+        use std::collections::hash_map::DefaultHasher;
+
+        fn hash_of<H: Hash>(value: &H) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            value.hash(&mut hasher);
+            hasher.finish()
+        }
+
+        let cell = OwnedRefCell::new(42);
+        let b1 = cell.borrow();
+        let b2 = cell.borrow();
+        assert!(b1 == b2);
+        assert_eq!(hash_of(&b1), hash_of(&42));
+        assert_eq!(hash_of(&b1), hash_of(&b2));
+    }
+
+    #[test]
+    #[cfg(feature = "recursion_detect")]
+    fn borrow_recursive_detects_reentry() {
+        let cell = OwnedRefCell::new(10);
+        let _plain = cell.borrow();
+        let outer = cell.borrow_recursive().unwrap();
+        assert_eq!(*outer, 10);
+        let err = cell.borrow_recursive().err().unwrap();
+        assert_eq!(err.to_string(), "already borrowed recursively");
+        drop(outer);
+        assert!(cell.borrow_recursive().is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "recursion_detect")]
+    fn borrow_recursive_tracks_cells_independently() {
+        let a = OwnedRefCell::new(1);
+        let b = OwnedRefCell::new(2);
+        let _a = a.borrow_recursive().unwrap();
+        assert!(b.borrow_recursive().is_ok());
+    }
+
+    #[test]
+    fn borrow_as_std_holds_read_borrow() {
+        let cell = OwnedRefCell::new(10);
+        {
+            let b = cell.borrow_as_std();
+            assert_eq!(*b, 10);
+            assert!(cell.try_borrow_mut().is_none());
+        }
+        assert!(cell.try_borrow_mut().is_some());
+    }
+
+    #[test]
+    fn vec_retain_and_drain_collect() {
+        let cell = OwnedRefCell::new(vec![1, 2, 3, 4]);
+        cell.retain(|v| v % 2 == 0);
+        assert_eq!(*cell.borrow(), vec![2, 4]);
+        assert_eq!(cell.drain_collect(), vec![2, 4]);
+        assert!(cell.borrow().is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn hashmap_retain_and_drain_collect() {
+        let cell = OwnedRefCell::new(HashMap::from([("a", 1), ("b", 2), ("c", 3)]));
+        cell.retain(|_, v| *v > 1);
+        assert_eq!(cell.borrow().len(), 2);
+        let drained = cell.drain_collect();
+        assert_eq!(drained.values().sum::<i32>(), 5);
+        assert!(cell.borrow().is_empty());
+    }
+
+    #[test]
+    fn panic_on_retain_when_already_borrowed() {
+        let cell = OwnedRefCell::new(vec![1, 2, 3]);
+        let _b = cell.borrow();
+        let cell_ref = AssertUnwindSafe(&cell);
+        let result = panic::catch_unwind(move || {
+            cell_ref.retain(|_| true);
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn try_into_inner_without_borrows() {
+        let cell = OwnedRefCell::new(String::from("value"));
+        assert_eq!(cell.try_into_inner().ok().unwrap(), "value");
+    }
+
+    #[test]
+    fn try_into_inner_with_outstanding_borrow() {
+        let cell = OwnedRefCell::new(10);
+        let b = cell.borrow();
+        let cell = cell.try_into_inner().err().unwrap();
+        assert_eq!(*b, 10);
+        drop(b);
+        assert_eq!(cell.try_into_inner().ok(), Some(10));
+    }
+
+    #[test]
+    fn assert_not_borrowed_after_guards_drop() {
+        let cell = OwnedRefCell::new(vec![1]);
+        cell.borrow_mut().push(2);
+        let total: i32 = cell.borrow().iter().sum();
+        assert_eq!(total, 3);
+        cell.assert_not_borrowed();
+    }
+
+    #[test]
+    #[should_panic(expected = "OwnedRefCell is still immutably borrowed by 2 reference(s)")]
+    fn assert_not_borrowed_panics_with_live_readers() {
+        let cell = OwnedRefCell::new(10);
+        let _b1 = cell.borrow();
+        let _b2 = cell.borrow();
+        cell.assert_not_borrowed();
+    }
+
+    #[test]
+    #[should_panic(expected = "OwnedRefCell is still mutably borrowed")]
+    fn assert_not_borrowed_panics_with_live_writer() {
+        let cell = OwnedRefCell::new(10);
+        let _b = cell.borrow_mut();
+        cell.assert_not_borrowed();
+    }
+
+    #[test]
+    fn split_at_mut_shares_write_borrow() {
+        let cell: OwnedRefCell<[i32]> = OwnedRefCell::from_box(Box::new([1, 2, 3, 4]));
+        let (mut left, mut right) = OwnedRefMut::split_at_mut(cell.borrow_mut(), 1);
+        left[0] = 10;
+        right[2] = 40;
+        assert_eq!(&*left, &[10]);
+        assert_eq!(&*right, &[2, 3, 40]);
+        drop(left);
+        assert!(cell.try_borrow().is_none());
+        drop(right);
+        assert_eq!(*cell.borrow(), [10, 2, 3, 40]);
+    }
+
+    #[test]
+    fn split_at_mut_out_of_bounds_panics_and_releases_borrow() {
+        let cell: OwnedRefCell<[i32]> = OwnedRefCell::from_box(Box::new([1, 2]));
+        let slice_ref = AssertUnwindSafe(&cell);
+        let result = panic::catch_unwind(move || {
+            OwnedRefMut::split_at_mut(slice_ref.borrow_mut(), 3);
+        });
+        assert!(result.is_err());
+        assert!(cell.try_borrow_mut().is_some());
+    }
+
+    #[test]
+    fn memo_computes_once() {
+        let memo = Memo::new();
+        let mut calls = 0;
+        let a = memo.get_or_compute(|| {
+            calls += 1;
+            String::from("value")
+        });
+        let b = memo.get_or_compute(|| {
+            calls += 1;
+            String::from("other")
+        });
+        assert_eq!(*a, "value");
+        assert_eq!(*b, "value");
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn memo_invalidate_recomputes() {
+        let memo = Memo::new();
+        assert_eq!(*memo.get_or_compute(|| 1), 1);
+        memo.invalidate();
+        assert_eq!(*memo.get_or_compute(|| 2), 2);
+    }
+
+    #[test]
+    fn panic_on_memo_invalidate_while_referenced() {
+        let memo = Memo::new();
+        let _value = memo.get_or_compute(|| 1);
+        let memo_ref = AssertUnwindSafe(&memo);
+        let result = panic::catch_unwind(move || {
+            memo_ref.invalidate();
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn borrow_mut_all_acquires_every_cell() {
+        let a = OwnedRefCell::new(1);
+        let b = OwnedRefCell::new(String::from("b"));
+        let (mut a_ref, mut b_ref) = borrow_mut_all!(a, b).unwrap();
+        *a_ref += 1;
+        b_ref.push('!');
+        assert!(a.try_borrow().is_none());
+        assert!(b.try_borrow().is_none());
+        drop((a_ref, b_ref));
+        assert_eq!(*a.borrow(), 2);
+        assert_eq!(*b.borrow(), "b!");
+    }
+
+    #[test]
+    fn borrow_mut_all_rolls_back_on_conflict() {
+        let a = OwnedRefCell::new(1);
+        let b = OwnedRefCell::new(2);
+        let c = OwnedRefCell::new(3);
+        let _c_ref = c.borrow();
+        assert!(borrow_mut_all!(a, b, c).is_none());
+        assert!(a.try_borrow_mut().is_some());
+        assert!(b.try_borrow_mut().is_some());
+    }
+
+    #[test]
+    fn borrow_mut_all_same_cell_twice_fails() {
+        let a = OwnedRefCell::new(1);
+        assert!(borrow_mut_all!(a, a).is_none());
+        assert!(a.try_borrow_mut().is_some());
+    }
+
+    #[test]
+    fn owned_ref_mut_into_owned_ref() {
+        let cell = OwnedRefCell::new(10);
+        let mut b = cell.borrow_mut();
+        *b = 20;
+        let b: OwnedRef<i32> = b.into();
+        assert_eq!(*b, 20);
+        {
+            let state = &cell.state;
+            assert!(!state.is_writing.get());
+            assert_eq!(state.reading_count.get(), 1);
+        }
+        assert!(cell.try_borrow().is_some());
+        assert!(cell.try_borrow_mut().is_none());
+        drop(b);
+        cell.assert_not_borrowed();
+    }
+
+    #[test]
+    fn same_cell_compares_identity() {
+        let a = OwnedRefCell::new(1);
+        let b = OwnedRefCell::new(1);
+        let _a_ref = a.borrow_mut();
+        assert!(a.same_cell(&a));
+        assert!(!a.same_cell(&b));
+        assert!(!b.same_cell(&a));
+    }
+
+    #[cfg(feature = "log")]
+    mod logged {
+        use super::*;
+        use std::sync::{Mutex, Once};
+        use std::thread::{self, ThreadId};
+
+        /// Every message logged so far, with the thread that logged it, since tests run in
+        /// parallel.
+        static MESSAGES: Mutex<Vec<(ThreadId, String)>> = Mutex::new(Vec::new());
+
+        struct TestLogger;
+
+        impl log::Log for TestLogger {
+            fn enabled(&self, _: &log::Metadata) -> bool {
+                true
+            }
+
+            fn log(&self, record: &log::Record) {
+                MESSAGES
+                    .lock()
+                    .unwrap()
+                    .push((thread::current().id(), record.args().to_string()));
+            }
+
+            fn flush(&self) {}
+        }
+
+        fn messages_for(tag: &str) -> Vec<String> {
+            let prefix = format!("released mut borrow {} after", tag);
+            release_messages()
+                .into_iter()
+                .filter(|message| message.starts_with(&prefix))
+                .collect()
+        }
+
+        /// Returns the write borrow release messages logged so far on the current thread.
+        fn release_messages() -> Vec<String> {
+            static INIT: Once = Once::new();
+            INIT.call_once(|| {
+                log::set_logger(&TestLogger).unwrap();
+                log::set_max_level(log::LevelFilter::Debug);
+            });
+            let current = thread::current().id();
+            MESSAGES
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(thread, message)| {
+                    *thread == current && message.starts_with("released mut borrow")
+                })
+                .map(|(_, message)| message.clone())
+                .collect()
+        }
+
+        #[test]
+        fn borrow_mut_logged_logs_on_release() {
+            assert!(messages_for("logged-release").is_empty());
+            let cell = OwnedRefCell::new(10);
+            let mut b = cell.borrow_mut_logged("logged-release");
+            *b = 20;
+            assert!(messages_for("logged-release").is_empty());
+            drop(b);
+            assert_eq!(messages_for("logged-release").len(), 1);
+        }
+
+        #[test]
+        fn plain_borrow_mut_does_not_log() {
+            let cell = OwnedRefCell::new(10);
+            drop(cell.borrow_mut_logged("logged-plain"));
+            let logged = release_messages().len();
+            assert_eq!(messages_for("logged-plain").len(), 1);
+            drop(cell.borrow_mut());
+            assert_eq!(release_messages().len(), logged);
+        }
+    }
+
+    #[test]
+    fn owned_ref_map_keeps_read_borrow() {
+        let cell = OwnedRefCell::new((1, String::from("one")));
+        let name = OwnedRef::map(cell.borrow(), |pair| &pair.1);
+        assert_eq!(*name, "one");
+        assert_eq!(cell.borrow_stats(), (1, false));
+        assert!(cell.try_borrow_mut().is_none());
+        drop(name);
+        cell.assert_not_borrowed();
+    }
+
+    #[test]
+    fn project_nested_fields() {
+        struct Inner {
+            values: Vec<i32>,
+        }
+        struct Outer {
+            inner: Inner,
+            pair: (i32, i32),
+        }
+
+        let cell = OwnedRefCell::new(Outer {
+            inner: Inner { values: vec![1] },
+            pair: (2, 3),
+        });
+        project!(mut cell => inner.values).push(2);
+        *project!(mut cell => pair.1) = 4;
+
+        let values = project!(cell => inner.values);
+        let second = project!(cell => pair.1);
+        assert_eq!(*values, vec![1, 2]);
+        assert_eq!(*second, 4);
+        assert_eq!(cell.state.reading_count.get(), 2);
+        assert!(cell.try_borrow_mut().is_none());
+    }
+
+    #[test]
+    fn try_replace_returns_previous_value() {
+        let cell = OwnedRefCell::new(String::from("old"));
+        assert_eq!(cell.try_replace(String::from("new")).unwrap(), "old");
+        assert_eq!(*cell.borrow(), "new");
+    }
+
+    #[test]
+    fn try_replace_busy_returns_value_intact() {
+        let cell = OwnedRefCell::new(String::from("old"));
+        let _b = cell.borrow();
+        let (t, err) = cell.try_replace(String::from("new")).unwrap_err();
+        assert_eq!(t, "new");
+        assert_eq!(err.to_string(), "already borrowed");
+        assert_eq!(*cell.borrow(), "old");
+    }
+
+    #[test]
+    fn borrow_or_default_clones_when_readable() {
+        let cell = OwnedRefCell::new(vec![1, 2]);
+        let _b = cell.borrow();
+        assert_eq!(cell.borrow_or_default(), vec![1, 2]);
+    }
+
+    #[test]
+    fn borrow_or_default_while_mutably_borrowed() {
+        let cell = OwnedRefCell::new(vec![1, 2]);
+        let _b = cell.borrow_mut();
+        assert_eq!(cell.borrow_or_default(), Vec::<i32>::new());
+    }
+
+    #[test]
+    #[cfg(feature = "diagnostics")]
+    fn guard_ids_are_unique_and_increasing() {
+        let cell = OwnedRefCell::new([1, 2]);
+        let a = cell.borrow();
+        let b = cell.borrow();
+        assert!(OwnedRef::id(&b) > OwnedRef::id(&a));
+        let b_id = OwnedRef::id(&b);
+        drop((a, b));
+
+        let value = cell.borrow_mut();
+        assert!(OwnedRefMut::id(&value) > b_id);
+        let value_id = OwnedRefMut::id(&value);
+        let first = OwnedRefMut::map(value, |value| &mut value[0]);
+        assert_eq!(OwnedRefMut::id(&first), value_id);
+        let first = OwnedRef::from(first);
+        assert_eq!(OwnedRef::id(&first), value_id);
+    }
+
+    #[test]
+    fn swap_map_computes_new_pair() {
+        let a = OwnedRefCell::new(vec![1, 2]);
+        let b = OwnedRefCell::new(vec![3]);
+        a.swap_map(&b, |mut a, b| {
+            a.extend(&b);
+            (a, Vec::new())
+        });
+        assert_eq!(*a.borrow(), vec![1, 2, 3]);
+        assert!(b.borrow().is_empty());
+    }
+
+    #[test]
+    fn swap_map_with_self_is_noop() {
+        let cell = OwnedRefCell::new(1);
+        cell.swap_map(&cell, |_, _| unreachable!());
+        assert_eq!(*cell.borrow(), 1);
+        cell.assert_not_borrowed();
+    }
+
+    #[test]
+    fn overwrite_from_clones_other_value() {
+        let a = OwnedRefCell::new(String::from("a"));
+        let b = OwnedRefCell::new(String::from("b"));
+        a.overwrite_from(&b);
+        assert_eq!(*a.borrow(), "b");
+        assert_eq!(*b.borrow(), "b");
+
+        let _a_ref = a.borrow();
+        a.overwrite_from(&a);
+    }
+
+    #[test]
+    #[should_panic(expected = "already borrowed")]
+    fn panic_on_overwrite_from_when_target_borrowed() {
+        let a = OwnedRefCell::new(1);
+        let b = OwnedRefCell::new(2);
+        let _a_ref = a.borrow();
+        a.overwrite_from(&b);
+    }
+
+    #[test]
+    #[cfg(feature = "recursion_detect")]
+    fn borrow_mut_guarded_detects_reentry() {
+        let cell = OwnedRefCell::new(1);
+        let guard = cell.borrow_mut_guarded().ok().unwrap();
+        assert!(cell.borrow_mut_guarded().is_err());
+        drop(guard);
+        assert!(cell.borrow_mut_guarded().is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "recursion_detect")]
+    fn borrow_mut_guarded_released_when_downgraded() {
+        let cell = OwnedRefCell::new(1);
+        let guard = OwnedRef::from(cell.borrow_mut_guarded().ok().unwrap());
+        drop(guard);
+        assert!(cell.borrow_mut_guarded().is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "recursion_detect")]
+    fn borrow_mut_guarded_detects_reentry_until_split_halves_are_released() {
+        let cell = OwnedRefCell::new((1, 2));
+        for first_dropped in [0, 1] {
+            let guard = cell.borrow_mut_guarded().ok().unwrap();
+            let (left, right) = OwnedRefMut::map_split(guard, |pair| (&mut pair.0, &mut pair.1));
+            let remaining = if first_dropped == 0 {
+                drop(left);
+                right
+            } else {
+                drop(right);
+                left
+            };
+            assert!(matches!(
+                cell.borrow_mut_guarded(),
+                Err(ReentrantBorrowError { .. })
+            ));
+            drop(OwnedRef::from(remaining));
+            assert!(cell.borrow_mut_guarded().is_ok());
+        }
+    }
+
+    #[test]
+    fn with_borrow_mut_result_separates_failures() {
+        let cell = OwnedRefCell::new(1);
+        let ok = cell.with_borrow_mut_result(|value| {
+            *value += 1;
+            Ok::<_, &str>(*value)
+        });
+        assert_eq!(ok.unwrap(), Ok(2));
+
+        let handler_err = cell.with_borrow_mut_result(|_| Err::<(), _>("handler failed"));
+        assert_eq!(handler_err.unwrap(), Err("handler failed"));
+        assert!(cell.try_borrow_mut().is_some());
+
+        let _value = cell.borrow();
+        let borrow_err = cell.with_borrow_mut_result(|_| -> Result<(), &str> {
+            unreachable!("must not run while borrowed")
+        });
+        assert!(borrow_err.is_err());
+    }
+
+    #[test]
+    fn get_disjoint_mut_shares_write_borrow() {
+        let cell = OwnedRefCell::new(vec![1, 2, 3]);
+        let [mut a, mut b] = cell.get_disjoint_mut([2, 0]).unwrap();
+        mem::swap(&mut *a, &mut *b);
+        drop(a);
+        assert!(cell.try_borrow().is_none());
+        drop(b);
+        assert_eq!(*cell.borrow(), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn get_disjoint_mut_rejects_bad_indices() {
+        let cell = OwnedRefCell::new(vec![1, 2, 3]);
+        assert!(cell.get_disjoint_mut([1, 1]).is_none());
+        assert!(cell.get_disjoint_mut([0, 3]).is_none());
+        assert!(cell.try_borrow_mut().is_some());
+
+        let _values = cell.borrow();
+        assert!(cell.get_disjoint_mut([0, 1]).is_none());
+    }
+
+    #[test]
+    fn freeze_all_downgrades_split_references() {
+        let cell = OwnedRefCell::new([1, 2, 3]);
+        let values = OwnedRefMut::map(cell.borrow_mut(), |values| &mut values[..]);
+        let (left, right) = OwnedRefMut::split_at_mut(values, 1);
+        let frozen = OwnedRefMut::freeze_all(vec![left, right]);
+        assert_eq!(frozen.iter().map(|part| part.len()).sum::<usize>(), 3);
+        {
+            let state = &cell.state;
+            assert!(!state.is_writing.get());
+            assert_eq!(state.writing_count.get(), 0);
+            assert_eq!(state.reading_count.get(), 2);
+        }
+        assert!(cell.try_borrow().is_some());
+        drop(frozen);
+        assert!(cell.try_borrow_mut().is_some());
+    }
+
+    #[test]
+    fn reentrant_borrow_mut_counts_depth() {
+        let cell = ReentrantOwnedRefCell::new(0);
+        assert_eq!(cell.depth(), 0);
+        let outer = unsafe { cell.borrow_mut() };
+        let inner = unsafe { cell.borrow_mut() };
+        let innermost = unsafe { cell.borrow_mut() };
+        assert_eq!(cell.depth(), 3);
+        drop(innermost);
+        drop(inner);
+        assert_eq!(cell.depth(), 1);
+        assert!(cell.cell.try_borrow().is_none());
+        drop(outer);
+        assert_eq!(cell.depth(), 0);
+        assert!(cell.cell.try_borrow_mut().is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "already borrowed")]
+    fn panic_on_reentrant_borrow_mut_while_immutably_borrowed() {
+        let cell = ReentrantOwnedRefCell::new(0);
+        let _value = cell.borrow();
+        let _value = unsafe { cell.borrow_mut() };
+    }
+
+    #[test]
+    #[should_panic(expected = "already borrowed")]
+    fn panic_on_reentrant_borrow_mut_while_split_half_is_frozen() {
+        let cell = ReentrantOwnedRefCell::new(vec![1, 2]);
+        let values = OwnedRefMut::map(unsafe { cell.borrow_mut() }, |values| values.as_mut_slice());
+        let (left, _right) = OwnedRefMut::split_at_mut(values, 1);
+        let _left = OwnedRef::from(left);
+        let _value = unsafe { cell.borrow_mut() };
+    }
+
+    #[test]
+    fn try_clone_respects_reader_cap() {
+        let cell = OwnedRefCell::new(5);
+        let first = cell.borrow();
+        let second = OwnedRef::try_clone(&first, 2).unwrap();
+        assert_eq!(*second, 5);
+        assert!(OwnedRef::try_clone(&first, 2).is_none());
+        assert_eq!(cell.state.reading_count.get(), 2);
+        drop(second);
+        assert!(OwnedRef::try_clone(&first, 2).is_some());
+    }
+
+    #[test]
+    fn snapshot_does_not_block_writers() {
+        let cell = OwnedRefCell::new(vec![1]);
+        let snapshot = cell.snapshot();
+        cell.borrow_mut().push(2);
+        assert_eq!(*snapshot, vec![1]);
+        assert_eq!(*cell.borrow(), vec![1, 2]);
+    }
+
+    #[test]
+    fn publish_only_when_unborrowed() {
+        let cell = OwnedRefCell::new(1);
+        assert_eq!(cell.publish(2), Ok(1));
+        let reader = cell.borrow();
+        assert_eq!(cell.publish(3), Err(3));
+        drop(reader);
+        let writer = cell.borrow_mut();
+        assert_eq!(cell.publish(4), Err(4));
+        drop(writer);
+        assert_eq!(*cell.borrow(), 2);
+    }
+
+    #[test]
+    fn borrow_stats_reports_readers_and_writer() {
+        let cell = OwnedRefCell::new(1);
+        assert_eq!(cell.borrow_stats(), (0, false));
+        let readers = (cell.borrow(), cell.borrow());
+        assert_eq!(cell.borrow_stats(), (2, false));
+        drop(readers);
+        let _writer = cell.borrow_mut();
+        assert_eq!(cell.borrow_stats(), (0, true));
+    }
+
+    #[test]
+    fn borrow_computed_computes_once_and_holds_borrow() {
+        let calls = Rc::new(std::cell::Cell::new(0));
+        let cell = OwnedRefCell::new(vec![1, 2, 3]);
+        let counter = Rc::clone(&calls);
+        let sum = cell.borrow_computed(move |values| {
+            counter.set(counter.get() + 1);
+            values.iter().sum::<i32>()
+        });
+        assert_eq!(calls.get(), 0);
+        assert_eq!(*sum, 6);
+        assert_eq!(*sum, 6);
+        assert_eq!(calls.get(), 1);
+        assert!(cell.try_borrow_mut().is_none());
+        drop(sum);
+        assert!(cell.try_borrow_mut().is_some());
+    }
+
+    #[test]
+    fn combinators_leave_state_consistent() {
+        let cell = OwnedRefCell::new(vec![1, 2, 3]);
+        let reader = cell.borrow();
+        cell.assert_consistent();
+        let writer = OwnedRef::try_upgrade(reader).ok().unwrap();
+        cell.assert_consistent();
+        let slice = OwnedRefMut::map(writer, |values| values.as_mut_slice());
+        let (left, right) = OwnedRefMut::split_at_mut(slice, 1);
+        cell.assert_consistent();
+        let left = OwnedRef::from(left);
+        cell.assert_consistent();
+        drop(right);
+        cell.assert_consistent();
+        drop(left);
+        cell.assert_consistent();
+        let [a, b] = cell.get_disjoint_mut([0, 2]).unwrap();
+        drop(OwnedRefMut::freeze_all(vec![a, b]));
+        cell.assert_consistent();
+        assert_eq!(cell.borrow_stats(), (0, false));
+    }
+
+    #[test]
+    fn try_upgrade_fails_while_split_half_is_writing() {
+        let cell = OwnedRefCell::new([1, 2]);
+        let values = OwnedRefMut::map(cell.borrow_mut(), |values| &mut values[..]);
+        let (left, right) = OwnedRefMut::split_at_mut(values, 1);
+        let left = OwnedRef::from(left);
+        let left = OwnedRef::try_upgrade(left).err().unwrap();
+        drop((left, right));
+        cell.assert_consistent();
+        assert!(cell.try_borrow_mut().is_some());
+    }
+
+    #[test]
+    fn borrow_mut_then_releases_borrow() {
+        let cell = OwnedRefCell::new(vec![1]);
+        let len = cell.borrow_mut_then(|values| {
+            values.push(2);
+            values.len()
+        });
+        assert_eq!(len, 2);
+        assert!(cell.try_borrow_mut().is_some());
+    }
+
+    #[test]
+    fn pointer_formatting_matches_value_address() {
+        let cell = OwnedRefCell::new([1, 2]);
+        let cell_addr = format!("{:p}", cell);
+        assert_eq!(format!("{:p}", cell.borrow()), cell_addr);
+        let second = OwnedRefMut::map(cell.borrow_mut(), |values| &mut values[1]);
+        assert_eq!(format!("{:p}", second), format!("{:p}", &*second));
+        assert_ne!(format!("{:p}", second), cell_addr);
+    }
+
+    #[test]
+    fn replace_and_read_returns_old_value_and_reader() {
+        let cell = OwnedRefCell::new(1);
+        let (old, new) = cell.replace_and_read(2);
+        assert_eq!((old, *new), (1, 2));
+        assert!(cell.try_borrow_mut().is_none());
+        assert!(cell.try_borrow().is_some());
+    }
+
+    #[cfg(feature = "async")]
+    mod asynchronous {
+        use std::{
+            future::Future,
+            pin::pin,
+            sync::{
+                atomic::{AtomicUsize, Ordering},
+                Arc,
+            },
+            task::{Context, Poll, Wake, Waker},
+        };
+
+        use super::*;
+
+        struct CountingWaker(AtomicUsize);
+
+        impl Wake for CountingWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        #[test]
+        fn borrow_mut_async_resolves_once_writer_drops() {
+            let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+            let waker = Waker::from(Arc::clone(&counter));
+            let mut cx = Context::from_waker(&waker);
+
+            let cell = OwnedRefCell::new(1);
+            let writer = cell.borrow_mut();
+            let mut future = pin!(cell.borrow_mut_async());
+            assert!(future.as_mut().poll(&mut cx).is_pending());
+            assert!(future.as_mut().poll(&mut cx).is_pending());
+            assert_eq!(counter.0.load(Ordering::SeqCst), 0);
+
+            drop(writer);
+            assert!(counter.0.load(Ordering::SeqCst) >= 1);
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(mut value) => *value += 1,
+                Poll::Pending => panic!("cell should be free"),
+            }
+            assert_eq!(*cell.borrow(), 2);
+        }
+
+        #[test]
+        fn waker_is_not_woken_while_readers_remain() {
+            let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+            let waker = Waker::from(Arc::clone(&counter));
+
+            let cell = OwnedRefCell::new(1);
+            let (first, second) = (cell.borrow(), cell.borrow());
+            cell.register_waker(&waker);
+            drop(first);
+            assert_eq!(counter.0.load(Ordering::SeqCst), 0);
+            drop(second);
+            assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        }
+    }
+
+    #[test]
+    fn try_borrow_map_projects_or_fails() {
+        let cell = OwnedRefCell::new((1, String::from("a")));
+        let name = cell.try_borrow_map(|value| value.1.as_str()).unwrap();
+        assert_eq!(&*name, "a");
+        assert!(cell.try_borrow_mut_map(|value| &mut value.0).is_err());
+        drop(name);
+
+        let mut first = cell.try_borrow_mut_map(|value| &mut value.0).unwrap();
+        *first += 1;
+        assert_eq!(
+            cell.try_borrow_map(|value| &value.0)
+                .err()
+                .unwrap()
+                .to_string(),
+            "already mutably borrowed"
+        );
+        drop(first);
+        assert_eq!(cell.borrow().0, 2);
+    }
+
+    #[test]
+    fn with_replaced_restores_original() {
+        let cell = OwnedRefCell::new("prod");
+        let seen = cell.with_replaced("test", || *cell.borrow());
+        assert_eq!(seen, "test");
+        assert_eq!(*cell.borrow(), "prod");
+    }
+
+    #[test]
+    fn with_replaced_restores_original_on_panic() {
+        let cell = OwnedRefCell::new(1);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            cell.with_replaced(2, || panic!("fixture failed"));
+        }));
+        assert!(result.is_err());
+        assert_eq!(*cell.borrow(), 1);
+    }
+
+    #[test]
+    fn borrow_fields_mut_shares_write_borrow() {
+        let cell = OwnedRefCell::new((1, String::from("a")));
+        let (mut count, mut name) = cell.borrow_fields_mut(|value| (&mut value.0, &mut value.1));
+        *count += 1;
+        name.push('b');
+        drop(count);
+        assert!(cell.try_borrow().is_none());
+        drop(name);
+        assert_eq!(*cell.borrow(), (2, String::from("ab")));
+    }
+
+    #[test]
+    #[cfg(feature = "depth_tracking")]
+    fn borrow_depth_counts_live_references() {
+        let a = OwnedRefCell::new([1, 2]);
+        let b = OwnedRefCell::new(3);
+        assert_eq!(borrow_depth(), 0);
+        let a_ref = a.borrow();
+        let b_ref = b.borrow_mut();
+        assert_eq!(borrow_depth(), 2);
+        let first = OwnedRef::map(a_ref, |values| &values[0]);
+        assert_eq!(borrow_depth(), 2);
+        drop((first, b_ref));
+        assert_eq!(borrow_depth(), 0);
+
+        let values = OwnedRefMut::map(a.borrow_mut(), |values| &mut values[..]);
+        let halves = OwnedRefMut::split_at_mut(values, 1);
+        assert_eq!(borrow_depth(), 2);
+        drop(halves);
+        assert_eq!(borrow_depth(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "depth_tracking")]
+    fn borrow_guarded_enforces_max_depth() {
+        let cells = [
+            OwnedRefCell::new(1),
+            OwnedRefCell::new(2),
+            OwnedRefCell::new(3),
+        ];
+        let first = cells[0].borrow_guarded(2).unwrap();
+        let second = cells[1].borrow_guarded(2).unwrap();
+        assert!(cells[2].borrow_guarded(2).is_err());
+        drop((first, second));
+        assert!(cells[2].borrow_guarded(2).is_ok());
+    }
+
+    #[test]
+    fn vec_for_each_mut_updates_every_element() {
+        let cell = OwnedRefCell::new(vec![1, 2, 3]);
+        cell.for_each_mut(|value| *value *= 10);
+        assert_eq!(*cell.borrow(), vec![10, 20, 30]);
+    }
+
+    #[test]
+    fn replace_boxed_swaps_allocations() {
+        let cell: OwnedRefCell<Box<[u8]>> = OwnedRefCell::new(vec![1; 4].into_boxed_slice());
+        let new = vec![2; 8].into_boxed_slice();
+        let new_addr = new.as_ptr();
+        let old = cell.replace_boxed(new);
+        assert_eq!(*old, [1; 4]);
+        assert_eq!(cell.borrow().as_ptr(), new_addr);
+    }
+
+    #[test]
+    fn drop_order_does_not_affect_reader_accounting() {
+        let orders = [
+            [0, 1, 2],
+            [0, 2, 1],
+            [1, 0, 2],
+            [1, 2, 0],
+            [2, 0, 1],
+            [2, 1, 0],
+        ];
+        for order in orders {
+            let cell = OwnedRefCell::new(1);
+            let mut readers: Vec<_> = (0..3).map(|_| Some(cell.borrow())).collect();
+            for (released, index) in order.into_iter().enumerate() {
+                readers[index] = None;
+                assert_eq!(cell.state.reading_count.get(), 2 - released);
+                cell.assert_consistent();
+            }
+            assert_eq!(
+                cell.borrow_ops(),
+                [BorrowOp::Borrow; 3]
+                    .into_iter()
+                    .chain([BorrowOp::Release; 3])
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn borrow_ops_records_transitions() {
+        let cell = OwnedRefCell::new([1, 2]);
+        let value = OwnedRef::try_upgrade(cell.borrow()).ok().unwrap();
+        let value = OwnedRefMut::map(value, |values| &mut values[..]);
+        let (left, right) = OwnedRefMut::split_at_mut(value, 1);
+        drop(OwnedRef::from(right));
+        drop(left);
+        assert_eq!(
+            cell.borrow_ops(),
+            [
+                BorrowOp::Borrow,
+                BorrowOp::Upgrade,
+                BorrowOp::BorrowMut,
+                BorrowOp::ReleaseMut,
+                BorrowOp::Borrow,
+                BorrowOp::Release,
+                BorrowOp::ReleaseMut,
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "bytemuck")]
+    fn borrow_as_reinterprets_and_holds_borrow() {
+        let cell = OwnedRefCell::new([7u32, 9]);
+        let bytes = cell.borrow_as::<[u8; 8]>();
+        assert_eq!(bytes[..4], 7u32.to_ne_bytes());
+        assert!(cell.try_borrow_mut().is_none());
+        drop(bytes);
+        assert!(cell.try_borrow_mut().is_some());
+    }
+
+    #[test]
+    #[cfg(feature = "bytemuck")]
+    fn panic_on_borrow_as_size_mismatch() {
+        let cell = OwnedRefCell::new([0u8; 3]);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| cell.borrow_as::<u32>()));
+        assert!(result.is_err());
+        assert!(cell.try_borrow_mut().is_some());
+    }
+
+    #[test]
+    fn borrow_mut_or_yield_retries_until_free() {
+        let cell = OwnedRefCell::new(1);
+        let mut reader = Some(cell.borrow());
+        let mut yields = 0;
+        let mut value = cell.borrow_mut_or_yield(|| {
+            yields += 1;
+            if yields == 3 {
+                reader = None;
+            }
+        });
+        *value += 1;
+        assert_eq!(yields, 3);
+    }
+
+    #[test]
+    fn try_borrow_mut_or_yield_gives_up() {
+        let cell = OwnedRefCell::new(1);
+        let _reader = cell.borrow();
+        let mut yields = 0;
+        assert!(cell.try_borrow_mut_or_yield(2, || yields += 1).is_none());
+        assert_eq!(yields, 2);
+    }
+
+    #[test]
+    fn dump_state_summarizes_borrows() {
+        let cell = OwnedRefCell::new(1);
+        let _readers = (cell.borrow(), cell.borrow());
+        assert!(cell.dump_state().starts_with("readers: 2, writing: false"));
+    }
+
+    #[test]
+    #[cfg(feature = "diagnostics")]
+    fn dump_state_includes_writer_location() {
+        let cell = OwnedRefCell::new(1);
+        let writer = cell.borrow_mut();
+        let dump = cell.dump_state();
+        assert!(dump.starts_with("readers: 0, writing: true, writer taken at src/lib.rs:"));
+        assert!(dump.ends_with(&format!("last guard id: {}", OwnedRefMut::id(&writer))));
+    }
+
+    #[test]
+    fn try_map_split_success_and_failure() {
+        enum Shape {
+            Pair(i32, i32),
+            Empty,
+        }
+
+        fn pair(shape: &mut Shape) -> Result<(&mut i32, &mut i32), &'static str> {
+            match shape {
+                Shape::Pair(a, b) => Ok((a, b)),
+                Shape::Empty => Err("not a pair"),
+            }
+        }
+
+        let cell = OwnedRefCell::new(Shape::Empty);
+        let (err, mut value) = OwnedRefMut::try_map_split(cell.borrow_mut(), pair)
+            .err()
+            .unwrap();
+        assert_eq!(err, "not a pair");
+        assert!(cell.try_borrow().is_none());
+        *value = Shape::Pair(1, 2);
+
+        let (mut a, b) = OwnedRefMut::try_map_split(value, pair).ok().unwrap();
+        *a += *b;
+        drop(b);
+        assert!(cell.try_borrow().is_none());
+        drop(a);
+        assert!(matches!(*cell.borrow(), Shape::Pair(3, 2)));
+    }
+
+    #[test]
+    fn version_detects_writes_between_reads() {
+        let cell = OwnedRefCell::new(1);
+        assert_eq!(cell.version(), 0);
+        let first = cell.borrow();
+        assert_eq!(OwnedRef::version(&first), 0);
+        drop(first);
+
+        *cell.borrow_mut() += 1;
+        assert_eq!(cell.version(), 1);
+        let second = cell.borrow();
+        assert_eq!(OwnedRef::version(&second), 1);
+        drop(second);
+
+        let downgraded = OwnedRef::from(cell.borrow_mut());
+        assert_eq!(OwnedRef::version(&downgraded), 2);
+        assert_eq!(cell.version(), 2);
+    }
+
+    #[test]
+    fn txn_guard_commit_keeps_changes() {
+        let cell = OwnedRefCell::new(String::from("a"));
+        let mut txn = cell.begin_mut();
+        txn.push('b');
+        assert!(cell.try_borrow().is_none());
+        TxnGuard::commit(txn);
+        assert_eq!(*cell.borrow(), "ab");
+    }
+
+    #[test]
+    fn txn_guard_rolls_back_on_drop() {
+        let cell = OwnedRefCell::new(String::from("a"));
+        let mut txn = cell.begin_mut();
+        txn.push('b');
+        drop(txn);
+        assert_eq!(*cell.borrow(), "a");
+    }
+
+    #[test]
+    fn txn_guard_rolls_back_on_panic() {
+        let cell = OwnedRefCell::new(String::from("a"));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut txn = cell.begin_mut();
+            txn.push('b');
+            panic!("edit failed");
+        }));
+        assert!(result.is_err());
+        assert_eq!(*cell.borrow(), "a");
+    }
+
+    #[test]
+    fn broadcast_adds_and_releases_n_readers() {
+        let cell = OwnedRefCell::new(5);
+        let reader = cell.borrow();
+        let subscribers = OwnedRef::broadcast(&reader, 4);
+        assert_eq!(subscribers.len(), 4);
+        assert!(subscribers.iter().all(|value| **value == 5));
+        assert_eq!(cell.state.reading_count.get(), 5);
+        drop(subscribers);
+        assert_eq!(cell.state.reading_count.get(), 1);
+    }
+
+    #[test]
+    fn from_box_stores_trait_objects() {
+        trait Shape {
+            fn area(&self) -> u32;
+            fn scale(&mut self, factor: u32);
+        }
+
+        struct Square(u32);
+        struct Rect(u32, u32);
+
+        impl Shape for Square {
+            fn area(&self) -> u32 {
+                self.0 * self.0
+            }
+            fn scale(&mut self, factor: u32) {
+                self.0 *= factor;
+            }
+        }
+
+        impl Shape for Rect {
+            fn area(&self) -> u32 {
+                self.0 * self.1
+            }
+            fn scale(&mut self, factor: u32) {
+                self.0 *= factor;
+                self.1 *= factor;
+            }
+        }
+
+        let shapes: Vec<OwnedRefCell<dyn Shape>> = vec![
+            OwnedRefCell::from_box(Box::new(Square(2))),
+            OwnedRefCell::from_box(Box::new(Rect(2, 3))),
+        ];
+        let first = shapes[0].borrow();
+        shapes[1].borrow_mut().scale(2);
+        assert_eq!(first.area(), 4);
+        assert_eq!(shapes[1].borrow().area(), 24);
+    }
+
+    #[test]
+    fn moving_cell_keeps_references_valid() {
+        let cell = OwnedRefCell::new(String::from("moved"));
+        let value = cell.borrow();
+        let moved = Box::new(cell);
+        assert_eq!(*value, "moved");
+        drop(value);
+        assert!(moved.try_borrow_mut().is_some());
+    }
+
+    #[test]
+    fn take_with_placeholder_keeps_write_borrow() {
+        let cell = OwnedRefCell::new(vec![1, 2]);
+        let (front, mut back) = cell.take_with_placeholder(Vec::with_capacity(2));
+        assert_eq!(front, vec![1, 2]);
+        assert!(cell.try_borrow().is_none());
+        back.push(3);
+        drop(back);
+        assert_eq!(*cell.borrow(), vec![3]);
+    }
+
+    #[test]
+    fn borrow_nested_mut_holds_both_layers() {
+        let cell = OwnedRefCell::new(OwnedRefCell::new(1));
+        let mut inner = cell.borrow_nested_mut();
+        *inner += 1;
+        assert!(cell.try_borrow_mut().is_none());
+        assert!(cell.borrow().try_borrow().is_none());
+        drop(inner);
+        assert_eq!(*cell.borrow().borrow(), 2);
+        assert!(cell.try_borrow_mut().is_some());
+    }
+
+    #[test]
+    fn weak_count_tracks_live_handles() {
+        let cell = OwnedRefCell::new(1);
+        let first = cell.downgrade();
+        let second = first.clone();
+        assert_eq!(cell.weak_count(), 2);
+        drop(first);
+        assert_eq!(cell.weak_count(), 1);
+        assert_eq!(*second.upgrade().unwrap(), 1);
+    }
+
+    #[test]
+    fn upgrade_all_fails_after_cell_dropped() {
+        let cells = [OwnedRefCell::new(1), OwnedRefCell::new(2)];
+        let weaks: Vec<_> = cells.iter().map(OwnedRefCell::downgrade).collect();
+        let writer = cells[1].borrow_mut();
+        let upgraded = WeakOwnedRef::upgrade_all(&weaks);
+        assert_eq!(upgraded[0].as_deref(), Some(&1));
+        assert!(upgraded[1].is_none());
+        drop((upgraded, writer));
+
+        drop(cells);
+        assert!(WeakOwnedRef::upgrade_all(&weaks)
+            .iter()
+            .all(Option::is_none));
+    }
+
+    #[test]
+    fn borrow_range_mut_narrows_to_window() {
+        let cell = OwnedRefCell::new(vec![1, 2, 3, 4]);
+        let mut window = cell.borrow_range_mut(1..3).unwrap();
+        window.iter_mut().for_each(|value| *value = 0);
+        assert!(cell.try_borrow().is_none());
+        drop(window);
+        assert_eq!(*cell.borrow(), vec![1, 0, 0, 4]);
+
+        assert!(cell.borrow_range_mut(2..5).is_none());
+        assert!(cell.try_borrow_mut().is_some());
+    }
+
+    #[cfg(feature = "callbacks")]
+    mod callbacks {
+        use std::cell::Cell;
+
+        use super::*;
+
+        #[test]
+        fn borrow_mut_with_callback_runs_after_release() {
+            let cell = Rc::new(OwnedRefCell::new(1));
+            let seen = Rc::new(Cell::new(0));
+            let (reader, sink) = (Rc::clone(&cell), Rc::clone(&seen));
+            let guard = cell.borrow_mut_with_callback(move || sink.set(*reader.borrow()));
+            let mut guard = OwnedRefMut::map(guard, |value| value);
+            *guard = 2;
+            assert_eq!(seen.get(), 0);
+            drop(guard);
+            assert_eq!(seen.get(), 2);
+        }
+
+        #[test]
+        fn borrow_mut_with_callback_runs_on_downgrade() {
+            let cell = OwnedRefCell::new(1);
+            let calls = Rc::new(Cell::new(0));
+            let counter = Rc::clone(&calls);
+            let guard = cell.borrow_mut_with_callback(move || counter.set(counter.get() + 1));
+            let reader = OwnedRef::from(guard);
+            assert_eq!(calls.get(), 1);
+            drop(reader);
+            assert_eq!(calls.get(), 1);
+        }
+
+        #[test]
+        fn borrow_mut_with_callback_waits_for_split_halves() {
+            let cell = Rc::new(OwnedRefCell::new((1, 2)));
+            let seen = Rc::new(Cell::new(None));
+            let (reader, sink) = (Rc::clone(&cell), Rc::clone(&seen));
+            let guard = cell.borrow_mut_with_callback(move || sink.set(Some(*reader.borrow())));
+            let (mut first, mut second) =
+                OwnedRefMut::map_split(guard, |(first, second)| (first, second));
+            *first = 10;
+            drop(first);
+            assert_eq!(seen.get(), None);
+            *second = 20;
+            drop(second);
+            assert_eq!(seen.get(), Some((10, 20)));
+        }
+
+        #[test]
+        fn borrow_mut_with_callback_waits_for_downgraded_halves() {
+            let cell = Rc::new(OwnedRefCell::new(vec![1, 2]));
+            let seen = Rc::new(Cell::new(0));
+            let (reader, sink) = (Rc::clone(&cell), Rc::clone(&seen));
+            let guard = cell.borrow_mut_with_callback(move || sink.set(reader.borrow().len()));
+            let values = OwnedRefMut::map(guard, |values| values.as_mut_slice());
+            let (first, second) = OwnedRefMut::split_at_mut(values, 1);
+            let first = OwnedRef::from(first);
+            assert_eq!(seen.get(), 0);
+            let second = OwnedRef::from(second);
+            assert_eq!(seen.get(), 2);
+            drop((first, second));
+            assert_eq!(seen.get(), 2);
+        }
+
+        #[test]
+        fn borrow_with_callback_runs_once_dropped() {
+            let cell = OwnedRefCell::new(1);
+            let calls = Rc::new(Cell::new(0));
+            let counter = Rc::clone(&calls);
+            let guard = cell.borrow_with_callback(move || counter.set(counter.get() + 1));
+            let other = cell.borrow();
+            drop(other);
+            assert_eq!(calls.get(), 0);
+            drop(guard);
+            assert_eq!(calls.get(), 1);
+        }
+    }
+
+    #[test]
+    fn try_borrow_read_write_rolls_back_on_conflict() {
+        let a = OwnedRefCell::new(2);
+        let b = OwnedRefCell::new(String::new());
+        let (a_ref, mut b_ref) = OwnedRefCell::try_borrow_read_write(&a, &b).unwrap();
+        b_ref.push_str(&a_ref.to_string());
+        drop((a_ref, b_ref));
+        assert_eq!(*b.borrow(), "2");
+
+        let _b_reader = b.borrow();
+        assert!(OwnedRefCell::try_borrow_read_write(&a, &b).is_none());
+        assert!(a.try_borrow_mut().is_some());
+        assert!(OwnedRefCell::try_borrow_read_write(&a, &a).is_none());
+        assert!(a.try_borrow_mut().is_some());
+    }
+
+    #[test]
+    fn run_borrow_model_follows_borrow_rules() {
+        let steps = run_borrow_model(&[
+            ModelOp::Borrow,
+            ModelOp::Borrow,
+            ModelOp::BorrowMut,
+            ModelOp::Upgrade(0),
+            ModelOp::Drop(1),
+            ModelOp::Upgrade(0),
+            ModelOp::Split(0),
+            ModelOp::Borrow,
+            ModelOp::Downgrade(1),
+            ModelOp::Drop(5),
+        ])
+        .unwrap();
+        let applied: Vec<bool> = steps.iter().map(|step| step.applied).collect();
+        assert_eq!(
+            applied,
+            [true, true, false, false, true, true, true, false, true, false]
+        );
+        assert_eq!(steps[1].readers, 2);
+        assert_eq!((steps[8].readers, steps[8].writing), (1, true));
+    }
+
+    #[test]
+    fn run_borrow_model_exhaustive_short_sequences() {
+        let alphabet = [
+            ModelOp::Borrow,
+            ModelOp::BorrowMut,
+            ModelOp::Drop(0),
+            ModelOp::Drop(1),
+            ModelOp::Downgrade(0),
+            ModelOp::Upgrade(0),
+            ModelOp::Split(0),
+        ];
+        let mut ops = Vec::new();
+        for a in alphabet {
+            for b in alphabet {
+                for c in alphabet {
+                    for d in alphabet {
+                        ops.clear();
+                        ops.extend([a, b, c, d]);
+                        if let Err(violation) = run_borrow_model(&ops) {
+                            panic!("{:?}: {}", ops, violation);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn local_cell_enforces_borrow_rules() {
+        let cell = LocalOwnedRefCell::new(vec![1]);
+        let first = cell.borrow();
+        let second = cell.borrow();
+        assert!(cell.try_borrow_mut().is_none());
+        assert_eq!((first.len(), second.len()), (1, 1));
+        drop((first, second));
+
+        let mut writer = cell.borrow_mut();
+        writer.push(2);
+        assert!(cell.try_borrow().is_none());
+        assert!(cell.try_borrow_mut().is_none());
+        drop(writer);
+        assert_eq!(*cell.borrow(), [1, 2]);
+    }
+
+    #[test]
+    fn local_cell_references_outlive_cell() {
+        let cell = LocalOwnedRefCell::new(String::from("kept"));
+        let reader = cell.borrow();
+        drop(cell);
+        assert_eq!(*reader, "kept");
+
+        let cell = LocalOwnedRefCell::new(vec![1]);
+        let mut writer = cell.borrow_mut();
+        drop(cell);
+        writer.push(2);
+        assert_eq!(*writer, [1, 2]);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn io_traits_forward_to_value() {
+        use std::io::{Cursor, Read, Write};
+
+        let cell = OwnedRefCell::new(Cursor::new(Vec::new()));
+        cell.borrow_mut().write_all(b"abc").unwrap();
+        writeln!(cell.borrow_mut(), "{}", 1).unwrap();
+        cell.borrow_mut().set_position(0);
+
+        let mut read = String::new();
+        cell.borrow_mut().read_to_string(&mut read).unwrap();
+        assert_eq!(read, "abc1\n");
+    }
+
+    #[test]
+    fn vec_cell_push_pop_and_remove() {
+        let cell = OwnedRefCell::new(vec![1, 2, 3]);
+        cell.push(4);
+        assert_eq!(cell.swap_remove(0), 1);
+        assert_eq!(*cell.borrow(), [4, 2, 3]);
+        assert_eq!(cell.remove(0), 4);
+        assert_eq!(cell.pop(), Some(3));
+        assert_eq!(cell.pop(), Some(2));
+        assert_eq!(cell.pop(), None);
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| cell.remove(0)));
+        assert!(result.is_err());
+        assert!(cell.try_borrow_mut().is_some());
+
+        let _reader = cell.borrow();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| cell.push(1)));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn transform_replaces_value() {
+        let cell = OwnedRefCell::new(Some(vec![1]));
+        cell.transform(|value| {
+            value.map(|mut v| {
+                v.push(2);
+                v
+            })
+        });
+        assert_eq!(*cell.borrow(), Some(vec![1, 2]));
+        cell.transform(|_| None);
+        assert_eq!(*cell.borrow(), None);
+
+        let _reader = cell.borrow();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| cell.transform(|value| value)));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn transform_aborts_when_f_panics() {
+        // Aborting takes down the whole process, so the panicking transform runs in a child
+        // process running only this test.
+        if std::env::var_os("OWNED_REF_CELL_TRANSFORM_CHILD").is_some() {
+            let cell = OwnedRefCell::new(String::from("state"));
+            cell.transform(|_| panic!("transition failed"));
+            return;
+        }
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "tests::transform_aborts_when_f_panics",
+                "--nocapture",
+            ])
+            .env("OWNED_REF_CELL_TRANSFORM_CHILD", "1")
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            assert_eq!(output.status.signal(), Some(6));
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("transition failed"));
+        assert!(!stderr.contains("double free"));
+    }
+
+    #[cfg(feature = "leak-detect")]
+    mod leaks {
+        use super::*;
+
+        #[test]
+        fn report_lists_named_cells_with_live_references() {
+            let config = OwnedRefCell::named("config", 1);
+            let cache = OwnedRefCell::named("cache", Vec::<i32>::new());
+            let _unnamed = OwnedRefCell::new(0).borrow_mut();
+            assert!(report_leaked_borrows().is_empty());
+
+            let reader = config.borrow();
+            let writer = cache.borrow_mut();
+            assert_eq!(
+                report_leaked_borrows(),
+                [
+                    LeakReport {
+                        name: "config",
+                        readers: 1,
+                        writing: false,
+                        cell_dropped: false,
+                    },
+                    LeakReport {
+                        name: "cache",
+                        readers: 0,
+                        writing: true,
+                        cell_dropped: false,
+                    },
+                ]
+            );
+            drop(reader);
+
+            drop(cache);
+            let report = report_leaked_borrows();
+            assert_eq!(report.len(), 1);
+            assert!(report[0].cell_dropped);
+            drop(writer);
+            assert!(report_leaked_borrows().is_empty());
+        }
+    }
+
+    #[test]
+    fn into_inner_returns_value() {
+        let cell = OwnedRefCell::new(vec![1, 2]);
+        cell.borrow_mut().push(3);
+        assert_eq!(cell.into_inner(), [1, 2, 3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Failed to move out of the cell: still borrowed")]
+    fn panic_on_into_inner_with_outstanding_borrow() {
+        let cell = OwnedRefCell::new(10);
+        let _b = cell.borrow();
+        cell.into_inner();
+    }
+
+    #[test]
+    fn replace_returns_previous_value() {
+        let cell = OwnedRefCell::new(1);
+        assert_eq!(cell.replace(2), 1);
+        assert_eq!(
+            cell.replace_with(|value| {
+                *value += 1;
+                *value * 10
+            }),
+            3
+        );
+        assert_eq!(*cell.borrow(), 30);
+    }
+
+    #[test]
+    #[should_panic(expected = "Failed to borrow mutably: already borrowed")]
+    fn panic_on_replace_while_borrowed() {
+        let cell = OwnedRefCell::new(1);
+        let _b = cell.borrow();
+        cell.replace(2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Failed to borrow mutably: already borrowed")]
+    fn panic_on_replace_with_while_borrowed() {
+        let cell = OwnedRefCell::new(1);
+        let _b = cell.borrow();
+        cell.replace_with(|value| *value);
+    }
+
+    #[test]
+    fn take_leaves_default_behind() {
+        let cell = OwnedRefCell::new(vec![1, 2, 3]);
+        let items = cell.take();
+        assert_eq!(items, [1, 2, 3]);
+        assert!(cell.borrow().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Failed to borrow mutably: already borrowed")]
+    fn panic_on_take_while_borrowed() {
+        let cell = OwnedRefCell::new(vec![1]);
+        let _b = cell.borrow();
+        cell.take();
+    }
+
+    #[test]
+    fn get_mut_mutates_without_borrowing() {
+        let mut cell = OwnedRefCell::new(vec![1]);
+        cell.get_mut().push(2);
+        assert_eq!(*cell.borrow(), [1, 2]);
+        assert_eq!(cell.borrow_ops(), [BorrowOp::Borrow, BorrowOp::Release]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Failed to borrow mutably: already borrowed")]
+    fn panic_on_get_mut_with_outstanding_reference() {
+        let mut cell = OwnedRefCell::new(1);
+        let _b = cell.borrow();
+        *cell.get_mut() = 2;
+    }
+
+    #[test]
+    fn default_cell_holds_default_value() {
+        #[derive(Default)]
+        struct Registry {
+            names: OwnedRefCell<Vec<String>>,
+        }
+
+        assert_eq!(*OwnedRefCell::<i32>::default().borrow(), 0);
+        let registry = Registry::default();
+        assert!(registry.names.borrow().is_empty());
+        assert!(registry.names.try_borrow_mut().is_some());
+    }
+
+    #[test]
+    fn debug_formats_value_or_borrowed_placeholder() {
+        let cell = OwnedRefCell::new(vec![1, 2]);
+        assert_eq!(format!("{:?}", cell), "OwnedRefCell { value: [1, 2] }");
+        let reader = cell.borrow();
+        assert_eq!(format!("{:?}", cell), "OwnedRefCell { value: [1, 2] }");
+        assert_eq!(format!("{:?}", reader), "[1, 2]");
+        drop(reader);
+
+        let writer = cell.borrow_mut();
+        assert_eq!(format!("{:?}", cell), "OwnedRefCell { value: <borrowed> }");
+        assert_eq!(format!("{:?}", writer), "[1, 2]");
+    }
+
+    #[test]
+    fn owned_ref_mut_map_keeps_write_borrow() {
+        struct Queue {
+            pending: Vec<u32>,
+            done: usize,
+        }
+
+        let cell = OwnedRefCell::new(Queue {
+            pending: vec![1],
+            done: 0,
         });
+        let mut pending = OwnedRefMut::map(cell.borrow_mut(), |queue| &mut queue.pending);
+        pending.push(2);
+        assert_eq!(cell.borrow_stats(), (0, true));
+        assert!(cell.try_borrow().is_none());
+        drop(pending);
+
+        cell.borrow_mut().done += 1;
+        let queue = cell.borrow();
+        assert_eq!((queue.pending.as_slice(), queue.done), (&[1, 2][..], 1));
+    }
+
+    #[test]
+    fn owned_ref_filter_map_narrows_or_hands_back() {
+        enum Shape {
+            Circle(f64),
+            Square(u32),
+        }
+
+        let cell = OwnedRefCell::new(Shape::Circle(1.5));
+        let radius = OwnedRef::filter_map(cell.borrow(), |shape| match shape {
+            Shape::Circle(radius) => Some(radius),
+            Shape::Square(_) => None,
+        })
+        .ok()
+        .unwrap();
+        assert_eq!(*radius, 1.5);
+        assert_eq!(cell.borrow_stats(), (1, false));
+        drop(radius);
+
+        *cell.borrow_mut() = Shape::Square(2);
+        let shape = OwnedRef::filter_map(cell.borrow(), |shape| match shape {
+            Shape::Circle(radius) => Some(radius),
+            Shape::Square(_) => None,
+        })
+        .err()
+        .unwrap();
+        assert!(matches!(*shape, Shape::Square(2)));
+        assert_eq!(cell.borrow_stats(), (1, false));
+        drop(shape);
+        cell.assert_not_borrowed();
+    }
+
+    #[test]
+    fn map_split_shares_write_borrow() {
+        let cell = OwnedRefCell::new((vec![1], vec![2]));
+        let (mut left, mut right) =
+            OwnedRefMut::map_split(cell.borrow_mut(), |(left, right)| (left, right));
+        left.push(3);
+        right.push(4);
+        assert_eq!(cell.borrow_stats(), (0, true));
+        drop(right);
+        assert!(cell.try_borrow().is_none());
+        left.push(5);
+        drop(left);
+        assert_eq!(*cell.borrow(), (vec![1, 3, 5], vec![2, 4]));
+    }
+
+    #[test]
+    fn clone_owned_ref_adds_read_borrow() {
+        let cell = OwnedRefCell::new(String::from("shared"));
+        let first = cell.borrow();
+        let clones: Vec<OwnedRef<String>> = (0..3).map(|_| first.clone()).collect();
+        assert_eq!(cell.borrow_stats(), (4, false));
+        assert!(clones.iter().all(|clone| **clone == "shared"));
+        drop(first);
+        assert!(cell.try_borrow_mut().is_none());
+        drop(clones);
+        assert!(cell.try_borrow_mut().is_some());
+    }
+
+    #[test]
+    fn downgrade_mutable_reference_keeps_reading() {
+        let cell = OwnedRefCell::new(vec![1]);
+        let mut writer = cell.borrow_mut();
+        writer.push(2);
+        let reader = OwnedRefMut::downgrade(writer);
+        assert_eq!(*reader, [1, 2]);
+        assert_eq!(cell.borrow_stats(), (1, false));
+        assert!(cell.try_borrow().is_some());
+        assert!(cell.try_borrow_mut().is_none());
+        drop(reader);
+        cell.assert_not_borrowed();
+    }
+
+    #[test]
+    fn as_ptr_points_at_value() {
+        let cell = OwnedRefCell::new(5);
+        let reader = cell.borrow();
+        let ptr = cell.as_ptr();
+        assert_eq!(unsafe { *ptr }, 5);
+        assert!(ptr::eq(ptr, &*reader));
+        assert_eq!(cell.borrow_stats(), (1, false));
+    }
+
+    #[test]
+    fn borrow_state_introspection() {
+        let cell = OwnedRefCell::new(0);
+        assert!(!cell.is_borrowed());
+        assert!(!cell.is_mutably_borrowed());
+        assert_eq!(cell.readers(), 0);
+
+        let first = cell.borrow();
+        assert!(cell.is_borrowed());
+        assert!(!cell.is_mutably_borrowed());
+        assert_eq!(cell.readers(), 1);
+
+        let second = cell.borrow();
+        assert_eq!(cell.readers(), 2);
+        drop((first, second));
+
+        let _writer = cell.borrow_mut();
+        assert!(cell.is_borrowed());
+        assert!(cell.is_mutably_borrowed());
+        assert_eq!(cell.readers(), 0);
+    }
+
+    #[test]
+    fn try_borrow_result_reports_typed_errors() {
+        fn first_len(cell: &OwnedRefCell<Vec<i32>>) -> Result<usize, BorrowError> {
+            let values = cell.try_borrow_result()?;
+            Ok(values.len())
+        }
+
+        let cell = OwnedRefCell::new(vec![1, 2]);
+        assert_eq!(first_len(&cell).unwrap(), 2);
+        let reader = cell.try_borrow_result().unwrap();
+        let err = cell.try_borrow_mut_result().err().unwrap();
+        assert_eq!(err.to_string(), "already borrowed");
+        drop(reader);
+
+        let _writer = cell.try_borrow_mut_result().unwrap();
+        let err = first_len(&cell).unwrap_err();
+        assert_eq!(err.to_string(), "already mutably borrowed");
+        let _: &dyn Error = &err;
+    }
+
+    #[test]
+    fn references_keep_value_alive_after_cell_dropped() {
+        let cell = OwnedRefCell::new(vec![String::from("a")]);
+        let reader = cell.borrow();
+        drop(cell);
+        assert_eq!(reader[0], "a");
+
+        let cell = OwnedRefCell::new(vec![String::from("a")]);
+        let mut writer = cell.borrow_mut();
+        drop(cell);
+        writer.push(String::from("b"));
+        assert_eq!(writer.concat(), "ab");
+    }
+
+    #[test]
+    fn orphaned_value_dropped_with_last_reference() {
+        let tracker = Rc::new(());
+        let cell = OwnedRefCell::new(Rc::clone(&tracker));
+        let first = cell.borrow();
+        let second = first.clone();
+        drop(cell);
+        assert_eq!(Rc::strong_count(&tracker), 2);
+        drop(first);
+        assert_eq!(Rc::strong_count(&tracker), 2);
+        drop(second);
+        assert_eq!(Rc::strong_count(&tracker), 1);
+
+        let cell = OwnedRefCell::new(Rc::clone(&tracker));
+        drop(cell.borrow());
+        drop(cell);
+        assert_eq!(Rc::strong_count(&tracker), 1);
+    }
+
+    #[test]
+    // The value is leaked on purpose, which Miri reports as an error.
+    #[cfg_attr(miri, ignore)]
+    fn orphaned_value_leaked_after_projection() {
+        let tracker = Rc::new(());
+        let cell = OwnedRefCell::new((Rc::clone(&tracker), 5));
+        let number = OwnedRef::map(cell.borrow(), |pair| &pair.1);
+        drop(cell);
+        assert_eq!(*number, 5);
+        drop(number);
+        assert_eq!(Rc::strong_count(&tracker), 2);
+    }
+
+    #[test]
+    fn orphaned_value_dropped_once_projections_are_gone() {
+        let tracker = Rc::new(());
+        let cell = OwnedRefCell::new((Rc::clone(&tracker), 5));
+        drop(OwnedRef::map(cell.borrow(), |pair| &pair.1));
+        let (left, right) =
+            OwnedRefMut::map_split(cell.borrow_mut(), |pair| (&mut pair.0, &mut pair.1));
+        drop((left, right));
+
+        let number = OwnedRef::map(cell.borrow(), |pair| &pair.1);
+        let whole = cell.borrow();
+        drop(cell);
+        drop(number);
+        assert_eq!(Rc::strong_count(&tracker), 2);
+        drop(whole);
+        assert_eq!(Rc::strong_count(&tracker), 1);
+    }
+
+    #[test]
+    fn try_upgrade_refuses_mapped_reference() {
+        let cell = OwnedRefCell::new((1, 2));
+        let second = OwnedRef::map(cell.borrow(), |pair| &pair.1);
+        let second = OwnedRef::try_upgrade(second).err().unwrap();
+        assert_eq!(*second, 2);
+        drop(second);
+
+        let second = OwnedRefMut::map(cell.borrow_mut(), |pair| &mut pair.1);
+        let mut second = OwnedRef::try_upgrade(OwnedRef::from(second)).ok().unwrap();
+        *second += 1;
+        drop(second);
+        assert_eq!(*cell.borrow(), (1, 3));
+    }
+
+    #[test]
+    fn try_borrow_fails_at_reader_limit() {
+        let cell = OwnedRefCell::new(0);
+        let reader = cell.borrow();
+        cell.state.reading_count.set(usize::MAX);
+        assert!(cell.try_borrow().is_none());
+        let result = panic::catch_unwind(AssertUnwindSafe(|| reader.clone()));
         assert!(result.is_err());
+        assert_eq!(cell.readers(), usize::MAX);
+        assert!(cell.try_borrow_mut().is_none());
+
+        cell.state.reading_count.set(1);
+        drop(reader);
+        cell.assert_not_borrowed();
+    }
+
+    #[test]
+    fn borrow_panics_name_caller_location() {
+        fn panic_message(f: impl FnOnce()) -> String {
+            let payload = panic::catch_unwind(AssertUnwindSafe(f)).unwrap_err();
+            payload
+                .downcast::<String>()
+                .map(|message| *message)
+                .unwrap()
+        }
+
+        let cell = OwnedRefCell::new(0);
+        let writer = cell.borrow_mut();
+        let line = line!() + 1;
+        let message = panic_message(|| drop(cell.borrow()));
+        assert!(message.starts_with("Failed to borrow: already mutably borrowed"));
+        assert!(message.contains(&format!("called at {}:{}:", file!(), line)));
+        drop(writer);
+
+        let _reader = cell.borrow();
+        let line = line!() + 1;
+        let message = panic_message(|| drop(cell.borrow_mut()));
+        assert!(message.starts_with("Failed to borrow mutably: already borrowed"));
+        assert!(message.contains(&format!("called at {}:{}:", file!(), line)));
+    }
+
+    #[cfg(feature = "diagnostics")]
+    #[test]
+    fn borrow_panic_names_conflicting_writer() {
+        let cell = OwnedRefCell::new(0);
+        let line = line!() + 1;
+        let _writer = cell.borrow_mut();
+        let payload = panic::catch_unwind(AssertUnwindSafe(|| drop(cell.borrow()))).unwrap_err();
+        let message = payload.downcast::<String>().unwrap();
+        assert!(message.contains(&format!("mutable borrow taken at {}:{}:", file!(), line)));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_round_trip() {
+        #[derive(serde::Serialize, serde::Deserialize)]
+        struct Config {
+            limits: OwnedRefCell<HashMap<String, i32>>,
+        }
+
+        let config = Config {
+            limits: OwnedRefCell::new(HashMap::from([("a".to_string(), 1), ("b".to_string(), 2)])),
+        };
+        let json = serde_json::to_string(&config).unwrap();
+        let decoded: Config = serde_json::from_str(&json).unwrap();
+        assert_eq!(*decoded.limits.borrow(), *config.limits.borrow());
+        assert!(!decoded.limits.is_borrowed());
+
+        let writer = config.limits.borrow_mut();
+        let err = serde_json::to_string(&config).unwrap_err();
+        assert!(err.to_string().contains("already mutably borrowed"));
+        drop(writer);
+        assert!(serde_json::to_string(&config).is_ok());
+    }
+
+    #[test]
+    fn borrow_semantics_over_cell_state() {
+        use ModelOp::*;
+
+        let steps = run_borrow_model(&[
+            Borrow,
+            Borrow,
+            BorrowMut,
+            Upgrade(0),
+            Drop(1),
+            Upgrade(0),
+            Borrow,
+            BorrowMut,
+            Split(0),
+            Downgrade(1),
+            Borrow,
+            Drop(0),
+            Drop(0),
+            BorrowMut,
+        ])
+        .unwrap();
+        let outcomes: Vec<_> = steps
+            .iter()
+            .map(|step| (step.applied, step.readers, step.writing))
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                (true, 1, false),
+                (true, 2, false),
+                (false, 2, false),
+                (false, 2, false),
+                (true, 1, false),
+                (true, 0, true),
+                (false, 0, true),
+                (false, 0, true),
+                (true, 0, true),
+                (true, 1, true),
+                (false, 1, true),
+                (true, 1, false),
+                (true, 0, false),
+                (true, 0, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn weak_upgrade_follows_cell_and_borrow_state() {
+        let cell = OwnedRefCell::new(String::from("value"));
+        let weak = cell.downgrade();
+        let reader = weak.upgrade().unwrap();
+        assert_eq!(*reader, "value");
+        assert_eq!(cell.readers(), 1);
+        drop(reader);
+
+        let writer = cell.borrow_mut();
+        assert!(weak.upgrade().is_none());
+        drop(writer);
+        assert!(weak.upgrade().is_some());
+
+        // A live reference keeps the borrow state around, but the cell itself is gone.
+        let reader = cell.borrow();
+        drop(cell);
+        assert!(weak.upgrade().is_none());
+        drop(reader);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn as_ref_and_as_mut_forward_to_value() {
+        fn takes(x: impl AsRef<str>) -> usize {
+            x.as_ref().len()
+        }
+
+        fn clear(mut bytes: impl AsMut<[u8]>) {
+            bytes.as_mut().fill(0);
+        }
+
+        let cell = OwnedRefCell::new(String::from("value"));
+        assert_eq!(takes(cell.borrow()), 5);
+        assert_eq!(takes(cell.borrow_mut()), 5);
+        assert!(cell.try_borrow_mut().is_some());
+
+        let cell = OwnedRefCell::new(vec![1u8, 2, 3]);
+        clear(cell.borrow_mut());
+        assert_eq!(*cell.borrow(), [0, 0, 0]);
+    }
+
+    #[test]
+    fn with_and_with_mut_release_borrow() {
+        let cell = OwnedRefCell::new(vec![1, 2]);
+        cell.with_mut(|values| values.push(3));
+        assert_eq!(cell.with(|values| values.iter().sum::<i32>()), 6);
+        assert!(!cell.is_borrowed());
+
+        let reader = cell.borrow();
+        assert_eq!(cell.try_with(|values| values.len()), Some(3));
+        assert_eq!(cell.try_with_mut(|values| values.pop()), None);
+        drop(reader);
+
+        let writer = cell.borrow_mut();
+        assert_eq!(cell.try_with(|values| values.len()), None);
+        assert_eq!(cell.try_with_mut(|values| values.len()), None);
+        drop(writer);
+        assert_eq!(cell.try_with_mut(|values| values.pop()), Some(Some(3)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Failed to borrow mutably: already borrowed")]
+    fn panic_on_with_mut_while_borrowed() {
+        let cell = OwnedRefCell::new(1);
+        let _reader = cell.borrow();
+        cell.with_mut(|value| *value += 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Failed to borrow: already mutably borrowed")]
+    fn panic_on_with_while_mutably_borrowed() {
+        let cell = OwnedRefCell::new(1);
+        let _writer = cell.borrow_mut();
+        cell.with(|value| *value);
+    }
+
+    #[test]
+    fn update_persists_mutation() {
+        let cell = OwnedRefCell::new(vec![1]);
+        cell.update(|values| values.push(2));
+        cell.update(|values| values[0] = 10);
+        assert_eq!(*cell.borrow(), [10, 2]);
+        assert!(!cell.is_borrowed());
+    }
+
+    #[test]
+    #[should_panic(expected = "Failed to borrow mutably: already borrowed")]
+    fn panic_on_update_while_borrowed() {
+        let cell = OwnedRefCell::new(vec![1]);
+        let _reader = cell.borrow();
+        cell.update(|values| values.clear());
+    }
+
+    #[test]
+    fn calls_through_references_to_closures() {
+        let calls = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&calls);
+        let cell: OwnedRefCell<dyn Fn() -> i32> = OwnedRefCell::from_box(Box::new(move || {
+            counter.set(counter.get() + 1);
+            counter.get()
+        }));
+        let shared = cell.into_shared();
+        let first = shared.borrow();
+        let second = shared.borrow();
+        assert_eq!((first(), second()), (1, 2));
+        drop((first, second));
+
+        let mut total = 0;
+        let cell: OwnedRefCell<dyn FnMut(i32) + '_> =
+            OwnedRefCell::from_box(Box::new(|value| total += value));
+        let mut add = cell.borrow_mut();
+        add(2);
+        add(3);
+        drop((add, cell));
+        assert_eq!(total, 5);
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn cells_compare_by_value() {
+        #[derive(PartialEq, Eq, Debug)]
+        struct Config {
+            name: OwnedRefCell<String>,
+        }
+
+        let first = Config {
+            name: OwnedRefCell::new(String::from("a")),
+        };
+        let second = Config {
+            name: OwnedRefCell::new(String::from("a")),
+        };
+        assert_eq!(first, second);
+        second.name.borrow_mut().push('b');
+        assert_ne!(first, second);
+
+        let reader = first.name.borrow();
+        assert!(first.name == first.name);
+        drop(reader);
+        assert!(!first.name.is_borrowed());
+    }
+
+    #[test]
+    #[should_panic(expected = "Failed to borrow: already mutably borrowed")]
+    fn panic_on_eq_while_mutably_borrowed() {
+        let first = OwnedRefCell::new(1);
+        let second = OwnedRefCell::new(1);
+        let _writer = second.borrow_mut();
+        let _ = first == second;
+    }
+
+    #[test]
+    #[allow(clippy::mutable_key_type)]
+    fn cells_order_and_hash_by_value() {
+        let mut cells: Vec<_> = [3, 1, 2].into_iter().map(OwnedRefCell::new).collect();
+        cells.sort();
+        let sorted: Vec<i32> = cells.iter().map(|cell| *cell.borrow()).collect();
+        assert_eq!(sorted, [1, 2, 3]);
+        assert!(cells[0] < cells[1] && cells[2] >= cells[1]);
+        assert_eq!(
+            OwnedRefCell::new(f64::NAN).partial_cmp(&OwnedRefCell::new(1.0)),
+            None
+        );
+
+        let ordered = BTreeSet::from([OwnedRefCell::new(2), OwnedRefCell::new(1)]);
+        assert_eq!(*ordered.first().unwrap().borrow(), 1);
+
+        let set: HashSet<_> = [1, 2, 1].into_iter().map(OwnedRefCell::new).collect();
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(&OwnedRefCell::new(2)));
+        assert!(cells.iter().all(|cell| !cell.is_borrowed()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Failed to borrow: already mutably borrowed")]
+    fn panic_on_cmp_while_mutably_borrowed() {
+        let first = OwnedRefCell::new(1);
+        let second = OwnedRefCell::new(2);
+        let _writer = first.borrow_mut();
+        let _ = first.cmp(&second);
+    }
+
+    #[test]
+    fn try_map_narrows_or_hands_back() {
+        let cell = OwnedRefCell::new(Some(vec![1]));
+        let mut values = OwnedRefMut::try_map(cell.borrow_mut(), Option::as_mut).unwrap();
+        values.push(2);
+        assert!(cell.try_borrow().is_none());
+        drop(values);
+        assert_eq!(*cell.borrow(), Some(vec![1, 2]));
+
+        let mut orig =
+            OwnedRefMut::try_map(cell.borrow_mut(), |values| values.as_mut()?.get_mut(5))
+                .err()
+                .unwrap();
+        assert!(cell.try_borrow().is_none());
+        *orig = None;
+        let orig = OwnedRefMut::try_map(orig, Option::as_mut).err().unwrap();
+        assert!(cell.try_borrow_mut().is_none());
+        drop(orig);
+        assert_eq!(*cell.borrow(), None);
+        cell.assert_not_borrowed();
+    }
+
+    #[test]
+    #[cfg(feature = "diagnostics")]
+    fn write_helpers_record_caller_location() {
+        let cell = OwnedRefCell::new(vec![1, 2]);
+        let mut seen = None;
+        let line = line!() + 1;
+        cell.replace_with(|values| {
+            seen = cell.current_writer_location();
+            values.clone()
+        });
+        let seen = seen.unwrap();
+        assert_eq!((seen.file(), seen.line()), (file!(), line));
+
+        let line = line!() + 1;
+        let elements = cell.get_disjoint_mut([0, 1]).unwrap();
+        let seen = cell.current_writer_location().unwrap();
+        assert_eq!((seen.file(), seen.line()), (file!(), line));
+        drop(elements);
+    }
+
+    #[cfg(feature = "diagnostics")]
+    #[test]
+    fn get_mut_panic_names_caller_and_writer() {
+        let mut cell = OwnedRefCell::new(0);
+        let writer_line = line!() + 1;
+        let _writer = cell.borrow_mut();
+        let line = line!() + 1;
+        let payload = panic::catch_unwind(AssertUnwindSafe(|| *cell.get_mut() = 1)).unwrap_err();
+        let message = payload.downcast::<String>().unwrap();
+        assert!(message.contains(&format!("called at {}:{}:", file!(), line)));
+        assert!(message.contains(&format!(
+            "mutable borrow taken at {}:{}:",
+            file!(),
+            writer_line
+        )));
     }
 }